@@ -0,0 +1,234 @@
+//! An append overlay on top of [CowVec](crate::CowVec).
+
+use crate::{CowVec, FastForeachItem, OwnedForEachItem};
+use std::ops::Index;
+
+/// A copy-on-write vector which supports appending items without cloning the borrowed
+/// items.
+///
+/// The borrowed items are kept as an immutable prefix (a [CowVec](crate::CowVec)), and
+/// appended items are stored in a separate owned tail. The prefix is only cloned if one of
+/// its items is mutated, or if a contiguous Vec is requested using to_mut, into_owned or
+/// into_cow_vec.
+///
+/// Since the items are not stored contiguously, CowVecAppend does not dereference to a
+/// slice. Use as_slices, iter or indexing to read the items.
+///
+/// ```
+/// # use cow_vec_item::CowVecAppend;
+/// let big_vec = vec![1, 2, 3];
+/// let mut overlay = CowVecAppend::from(&big_vec);
+/// overlay.push(4);
+/// overlay.extend(vec![5, 6]);
+/// assert_eq!(overlay.len(), 6);
+/// assert_eq!(overlay[4], 5);
+/// assert_eq!(overlay.is_owned(), false); // big_vec has not been cloned
+/// assert_eq!(overlay.into_owned(), vec![1, 2, 3, 4, 5, 6]);
+/// ```
+pub struct CowVecAppend<'extvec, T> {
+    prefix: CowVec<'extvec, T>,
+    tail: Vec<T>,
+}
+
+impl<'extvec, T: Clone> CowVecAppend<'extvec, T> {
+    /// Creates a CowVecAppend which borrows the given Vec.
+    #[allow(clippy::ptr_arg)]
+    pub fn from(vec: &'extvec Vec<T>) -> CowVecAppend<'extvec, T> {
+        CowVecAppend::from_slice(vec)
+    }
+    /// Creates a CowVecAppend which borrows the given slice.
+    pub fn from_slice(slice: &'extvec [T]) -> CowVecAppend<'extvec, T> {
+        CowVecAppend::from_cow_vec(CowVec::from_slice(slice))
+    }
+    /// Creates a CowVecAppend using the given CowVec as prefix.
+    pub fn from_cow_vec(prefix: CowVec<'extvec, T>) -> CowVecAppend<'extvec, T> {
+        CowVecAppend {
+            prefix,
+            tail: Vec::new(),
+        }
+    }
+
+    /// Returns true if the prefix no longer borrows the initial Vec.
+    pub fn is_owned(&self) -> bool {
+        self.prefix.is_owned()
+    }
+    /// The total number of items, prefix and appended.
+    pub fn len(&self) -> usize {
+        self.prefix.len() + self.tail.len()
+    }
+    /// Returns true if there are no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns the prefix and the appended items, as two slices.
+    pub fn as_slices(&self) -> (&[T], &[T]) {
+        (&self.prefix, &self.tail)
+    }
+    /// Returns a reference to the item at the given index, or None if out of bounds.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let prefix_len = self.prefix.len();
+        if index < prefix_len {
+            self.prefix.get(index)
+        } else {
+            self.tail.get(index - prefix_len)
+        }
+    }
+    /// Returns a mutable reference to the item at the given index, or None if out of bounds.
+    /// If the item is part of the borrowed prefix, the prefix is cloned.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        let prefix_len = self.prefix.len();
+        if index < prefix_len {
+            self.prefix.get_mut(index)
+        } else {
+            self.tail.get_mut(index - prefix_len)
+        }
+    }
+    /// Iterate over all items, prefix first.
+    pub fn iter(&self) -> std::iter::Chain<std::slice::Iter<'_, T>, std::slice::Iter<'_, T>> {
+        self.prefix.iter().chain(self.tail.iter())
+    }
+
+    /// Appends an item. This never clones the prefix.
+    pub fn push(&mut self, value: T) {
+        self.tail.push(value);
+    }
+    /// Removes the last item and returns it, or None if empty. If there are no appended
+    /// items left, this narrows the prefix, cloning only the removed item.
+    pub fn pop(&mut self) -> Option<T> {
+        self.tail.pop().or_else(|| self.prefix.pop())
+    }
+
+    /// Like [CowVec::fast_for_each_mut](crate::CowVec::fast_for_each_mut). The prefix
+    /// is only cloned if one of its items is mutated.
+    pub fn fast_for_each_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut dyn FastForeachItem<Target = T>),
+    {
+        self.prefix.fast_for_each_mut(&mut f);
+        for item in self.tail.iter_mut() {
+            f(&mut OwnedForEachItem::untracked(item));
+        }
+    }
+
+    /// Moves the appended items into the prefix, taking ownership of it, and returns
+    /// a mutable reference to the resulting Vec.
+    pub fn to_mut(&mut self) -> &mut Vec<T> {
+        let vec = self.prefix.to_mut();
+        vec.append(&mut self.tail);
+        vec
+    }
+    /// Converts into a CowVec. If nothing was appended, the returned CowVec is
+    /// the prefix, which may still be borrowing.
+    pub fn into_cow_vec(mut self) -> CowVec<'extvec, T> {
+        if !self.tail.is_empty() {
+            self.to_mut();
+        }
+        self.prefix
+    }
+    /// Returns all the items as a Vec, cloning the prefix if it is still borrowed.
+    pub fn into_owned(self) -> Vec<T> {
+        if self.prefix.is_owned() {
+            let mut vec = self.prefix.into_owned();
+            vec.extend(self.tail);
+            vec
+        } else {
+            let mut vec = Vec::with_capacity(self.prefix.len() + self.tail.len());
+            vec.extend_from_slice(&self.prefix);
+            vec.extend(self.tail);
+            vec
+        }
+    }
+}
+
+impl<'extvec, T: Clone> Extend<T> for CowVecAppend<'extvec, T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.tail.extend(iter);
+    }
+}
+
+impl<'extvec, T: Clone> Index<usize> for CowVecAppend<'extvec, T> {
+    type Output = T;
+    fn index(&self, index: usize) -> &T {
+        match self.get(index) {
+            Some(item) => item,
+            None => panic!(
+                "cow_vec_item: index {} out of range for CowVecAppend of length {}",
+                index,
+                self.len()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CowVecAppend;
+    use crate::CowVec;
+
+    #[test]
+    fn test_append_keeps_borrowing() {
+        let v = vec![1i32, 2, 3];
+        let mut temp = CowVecAppend::from(&v);
+        temp.push(4);
+        temp.extend(vec![5, 6]);
+        assert!(!temp.is_owned());
+        assert_eq!(temp.len(), 6);
+        assert_eq!(temp.as_slices(), (&[1, 2, 3][..], &[4, 5, 6][..]));
+        assert_eq!(temp.iter().copied().collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(temp[2], 3);
+        assert_eq!(temp[3], 4);
+        assert_eq!(temp.get(6), None);
+
+        *temp.get_mut(4).unwrap() = 50;
+        assert!(!temp.is_owned());
+        *temp.get_mut(0).unwrap() = 10;
+        assert!(temp.is_owned());
+        assert_eq!(temp.as_slices(), (&[10, 2, 3][..], &[4, 50, 6][..]));
+        assert_eq!(v, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_append_fast_for_each() {
+        let v = vec![1i32, 2];
+        let mut temp = CowVecAppend::from(&v);
+        temp.push(3);
+        temp.fast_for_each_mut(|item| {
+            if **item == 3 {
+                **item = 30;
+            }
+        });
+        assert!(!temp.is_owned());
+        temp.fast_for_each_mut(|item| **item += 1);
+        assert!(temp.is_owned());
+        assert_eq!(temp.into_owned(), vec![2, 3, 31]);
+    }
+
+    #[test]
+    fn test_append_pop() {
+        let v = vec![1i32, 2];
+        let mut temp = CowVecAppend::from(&v);
+        temp.push(3);
+        assert_eq!(temp.pop(), Some(3));
+        assert_eq!(temp.pop(), Some(2));
+        assert!(!temp.is_owned());
+        assert_eq!(temp.len(), 1);
+        assert!(!temp.into_cow_vec().is_owned());
+    }
+
+    #[test]
+    fn test_append_into_cow_vec() {
+        let v = vec![1i32, 2];
+        let mut temp = CowVecAppend::from_cow_vec(CowVec::from(&v));
+        temp.push(3);
+        let mut cowvec = temp.into_cow_vec();
+        assert!(cowvec.is_owned());
+        assert_eq!(*cowvec, [1, 2, 3]);
+        cowvec.push(4);
+        assert_eq!(cowvec.into_owned(), vec![1, 2, 3, 4]);
+
+        let mut temp = CowVecAppend::from(&v);
+        temp.push(3);
+        temp.to_mut().insert(0, 0);
+        assert_eq!(temp.as_slices(), (&[0, 1, 2, 3][..], &[][..]));
+    }
+}
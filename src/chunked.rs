@@ -0,0 +1,225 @@
+//! A copy-on-write vector which clones chunks of items independently.
+
+use crate::{CowVec, CowVecItemWrapper, FastForeachItem};
+use std::ops::Index;
+
+/// A copy-on-write vector split into fixed size chunks, each of which is cloned
+/// independently, much like copy-on-write of memory pages.
+///
+/// Each chunk is a [CowVec](crate::CowVec) borrowing part of the initial Vec. Writing an
+/// item through iter_mut, fast_for_each_mut or get_mut only clones the chunk containing
+/// that item, so a single write costs O(chunk_size) instead of O(len).
+///
+/// Since the items are not stored contiguously once a chunk has been cloned,
+/// CowVecChunked does not dereference to a slice. Use get, iter or indexing to read the
+/// items.
+///
+/// ```
+/// # use cow_vec_item::CowVecChunked;
+/// let big_vec: Vec<u32> = (0..1000).collect();
+/// let mut chunked = CowVecChunked::from(&big_vec, 64);
+/// for mut item in chunked.iter_mut() {
+///     if *item == 500 {
+///         *item = 0; // Only the 64 items around this one are cloned
+///     }
+/// }
+/// assert_eq!(chunked[500], 0);
+/// assert_eq!(chunked.owned_chunk_count(), 1);
+/// ```
+pub struct CowVecChunked<'extvec, T> {
+    source: &'extvec [T],
+    chunk_size: usize,
+    chunks: Vec<CowVec<'extvec, T>>,
+}
+
+impl<'extvec, T: Clone> CowVecChunked<'extvec, T> {
+    /// Creates a CowVecChunked which borrows the given Vec, using chunks of
+    /// chunk_size items.
+    ///
+    /// Panics if chunk_size is 0.
+    #[allow(clippy::ptr_arg)]
+    pub fn from(vec: &'extvec Vec<T>, chunk_size: usize) -> CowVecChunked<'extvec, T> {
+        CowVecChunked::from_slice(vec, chunk_size)
+    }
+    /// Creates a CowVecChunked which borrows the given slice, using chunks of
+    /// chunk_size items.
+    ///
+    /// Panics if chunk_size is 0.
+    pub fn from_slice(slice: &'extvec [T], chunk_size: usize) -> CowVecChunked<'extvec, T> {
+        if chunk_size == 0 {
+            panic!("cow_vec_item: chunk_size must be non-zero");
+        }
+        CowVecChunked {
+            source: slice,
+            chunk_size,
+            chunks: slice.chunks(chunk_size).map(CowVec::from_slice).collect(),
+        }
+    }
+
+    /// Returns true if any chunk has been cloned.
+    pub fn is_owned(&self) -> bool {
+        self.chunks.iter().any(|chunk| chunk.is_owned())
+    }
+    /// Returns the number of chunks which have been cloned.
+    pub fn owned_chunk_count(&self) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.is_owned()).count()
+    }
+    /// The number of items in each chunk (the last chunk may be shorter).
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+    /// The number of items.
+    pub fn len(&self) -> usize {
+        self.source.len()
+    }
+    /// Returns true if there are no items.
+    pub fn is_empty(&self) -> bool {
+        self.source.is_empty()
+    }
+    /// Returns a reference to the item at the given index, or None if out of bounds.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.chunks
+            .get(index / self.chunk_size)?
+            .get(index % self.chunk_size)
+    }
+    /// Returns a mutable reference to the item at the given index, or None if out of
+    /// bounds. This clones the chunk containing the item.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        let chunk = self.chunks.get_mut(index / self.chunk_size)?;
+        chunk.get_mut(index % self.chunk_size)
+    }
+    /// Iterate over all items.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.chunks.iter().flat_map(|chunk| chunk.iter())
+    }
+
+    /// Like [CowVec::iter_mut](crate::CowVec::iter_mut), but writing an item only clones
+    /// the chunk containing it.
+    pub fn iter_mut<'cowvec>(
+        &'cowvec mut self,
+    ) -> impl Iterator<Item = CowVecItemWrapper<'extvec, 'cowvec, T>> + 'cowvec
+    where
+        'extvec: 'cowvec,
+    {
+        self.chunks.iter_mut().flat_map(|chunk| chunk.iter_mut())
+    }
+
+    /// Like [CowVec::fast_for_each_mut](crate::CowVec::fast_for_each_mut), but writing
+    /// an item only clones the chunk containing it.
+    pub fn fast_for_each_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut dyn FastForeachItem<Target = T>),
+    {
+        for chunk in self.chunks.iter_mut() {
+            chunk.fast_for_each_mut(&mut f);
+        }
+    }
+
+    /// Returns all the items as a Vec, cloning the chunks which are still borrowed.
+    pub fn into_owned(self) -> Vec<T> {
+        let mut vec = Vec::with_capacity(self.source.len());
+        for chunk in self.chunks {
+            if chunk.is_owned() {
+                vec.extend(chunk.into_owned());
+            } else {
+                vec.extend_from_slice(&chunk);
+            }
+        }
+        vec
+    }
+    /// Converts into a CowVec. If no chunk has been cloned, the returned CowVec still
+    /// borrows the initial Vec.
+    pub fn into_cow_vec(self) -> CowVec<'extvec, T> {
+        if self.is_owned() {
+            CowVec::from_owned(self.into_owned())
+        } else {
+            CowVec::from_slice(self.source)
+        }
+    }
+}
+
+impl<'extvec, T: Clone> Index<usize> for CowVecChunked<'extvec, T> {
+    type Output = T;
+    fn index(&self, index: usize) -> &T {
+        match self.get(index) {
+            Some(item) => item,
+            None => panic!(
+                "cow_vec_item: index {} out of range for CowVecChunked of length {}",
+                index,
+                self.len()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CowVecChunked;
+
+    #[test]
+    fn test_chunked_clones_only_written_chunks() {
+        let v: Vec<i32> = (0..10).collect();
+        let mut temp = CowVecChunked::from(&v, 3);
+        for mut item in temp.iter_mut() {
+            if *item == 4 || *item == 9 {
+                *item *= 10;
+            }
+        }
+        assert_eq!(temp.owned_chunk_count(), 2);
+        assert_eq!(temp[4], 40);
+        assert_eq!(temp[9], 90);
+        assert_eq!(temp.get(10), None);
+        assert_eq!(
+            temp.iter().copied().collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 40, 5, 6, 7, 8, 90]
+        );
+
+        temp.fast_for_each_mut(|item| {
+            if **item == 0 {
+                **item = -1;
+            }
+        });
+        assert_eq!(temp.owned_chunk_count(), 3);
+        *temp.get_mut(7).unwrap() = 70;
+        assert_eq!(temp.owned_chunk_count(), 4);
+        assert_eq!(temp.into_owned(), vec![-1, 1, 2, 3, 40, 5, 6, 70, 8, 90]);
+        assert_eq!(v, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_chunked_reading_keeps_borrowing() {
+        let v: Vec<i32> = (0..10).collect();
+        let mut temp = CowVecChunked::from(&v, 4);
+        let sum: i32 = temp.iter_mut().map(|item| *item).sum();
+        assert_eq!(sum, 45);
+        temp.fast_for_each_mut(|_item| {});
+        assert!(!temp.is_owned());
+        assert!(!temp.into_cow_vec().is_owned());
+    }
+
+    #[test]
+    fn test_chunked_empty_and_zero_size() {
+        let v: Vec<i32> = Vec::new();
+        let mut temp = CowVecChunked::from(&v, 4);
+        assert_eq!(temp.iter_mut().count(), 0);
+        assert!(temp.is_empty());
+
+        let v = vec![(); 5];
+        let mut temp = CowVecChunked::from(&v, 2);
+        let mut count = 0;
+        temp.fast_for_each_mut(|item| {
+            **item = ();
+            count += 1;
+        });
+        assert_eq!(count, 5);
+        assert_eq!(temp.owned_chunk_count(), 3);
+        assert_eq!(temp.into_owned().len(), 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_chunked_zero_chunk_size() {
+        let v = vec![1i32];
+        let _ = CowVecChunked::from(&v, 0);
+    }
+}
@@ -0,0 +1,204 @@
+//! A copy-on-write view of a mutably borrowed Vec, whose changes can be written back.
+
+use crate::{CowVec, CowVecContent, CowVecIter, CowVecLendingIter, FastForeachItem, Patch};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut, Index, IndexMut};
+use std::slice::SliceIndex;
+
+/// A [CowVec](crate::CowVec) created from a mutably borrowed Vec, using
+/// [CowVec::from_mut](crate::CowVec::from_mut).
+///
+/// It behaves like a CowVec borrowing the Vec: the Vec is only cloned once an item is
+/// mutated, and the Vec itself is never changed while the CowVecMut is alive. The changes
+/// can then either be written back into the Vec using commit, or discarded using
+/// rollback (or by dropping the CowVecMut).
+///
+/// Commit only moves the modified items back into the Vec, keeping its allocation. See
+/// [CowVec::is_modified](crate::CowVec::is_modified) for which writes are tracked.
+///
+/// ```
+/// # use cow_vec_item::CowVec;
+/// let mut shared = vec![1, 2, 3];
+/// let mut speculative = CowVec::from_mut(&mut shared);
+/// for mut item in speculative.iter_mut() {
+///     if *item == 2 {
+///         *item = 20;
+///     }
+/// }
+/// let looks_good = speculative.contains(&20);
+/// if looks_good {
+///     speculative.commit();
+/// } else {
+///     speculative.rollback();
+/// }
+/// assert_eq!(shared, vec![1, 20, 3]);
+/// ```
+pub struct CowVecMut<'extvec, T> {
+    // The CowVec borrows the Vec pointed to by source. It is never handed out, since
+    // anything borrowing the Vec must be gone before commit writes to it.
+    cowvec: CowVec<'extvec, T>,
+    source: *mut Vec<T>,
+    phantom: PhantomData<&'extvec mut Vec<T>>,
+}
+
+impl<'extvec, T: Clone> CowVecMut<'extvec, T> {
+    pub(crate) fn new(vec: &'extvec mut Vec<T>) -> CowVecMut<'extvec, T> {
+        let source = vec as *mut Vec<T>;
+        CowVecMut {
+            // Safe since the Vec is only written to in commit, which first consumes
+            // the CowVec.
+            cowvec: CowVec::from_slice(unsafe { &*source }),
+            source,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns true if the borrowed Vec has been cloned.
+    pub fn is_owned(&self) -> bool {
+        self.cowvec.is_owned()
+    }
+    /// See [CowVec::iter_mut](crate::CowVec::iter_mut).
+    pub fn iter_mut<'cowvec>(&'cowvec mut self) -> CowVecIter<'extvec, 'cowvec, T> {
+        self.cowvec.iter_mut()
+    }
+    /// See [CowVec::lending_iter_mut](crate::CowVec::lending_iter_mut).
+    pub fn lending_iter_mut<'cowvec>(&'cowvec mut self) -> CowVecLendingIter<'extvec, 'cowvec, T> {
+        self.cowvec.lending_iter_mut()
+    }
+    /// See [CowVec::fast_for_each_mut](crate::CowVec::fast_for_each_mut).
+    pub fn fast_for_each_mut<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut dyn FastForeachItem<Target = T>),
+    {
+        self.cowvec.fast_for_each_mut(f)
+    }
+    /// See [CowVec::fast_for_each_mut_indexed](crate::CowVec::fast_for_each_mut_indexed).
+    pub fn fast_for_each_mut_indexed<F>(&mut self, f: F)
+    where
+        F: FnMut(usize, &mut dyn FastForeachItem<Target = T>),
+    {
+        self.cowvec.fast_for_each_mut_indexed(f)
+    }
+    /// See [CowVec::get_mut](crate::CowVec::get_mut).
+    pub fn get_mut<I: SliceIndex<[T]> + Clone>(&mut self, index: I) -> Option<&mut I::Output> {
+        self.cowvec.get_mut(index)
+    }
+    /// See [CowVec::is_modified](crate::CowVec::is_modified).
+    pub fn is_modified(&self, index: usize) -> bool {
+        self.cowvec.is_modified(index)
+    }
+    /// See [CowVec::modified_indices](crate::CowVec::modified_indices).
+    pub fn modified_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.cowvec.modified_indices()
+    }
+
+    /// Write the changes back into the borrowed Vec. If the contents are still borrowed,
+    /// nothing was changed and the Vec is left untouched. Otherwise only the modified
+    /// items are moved into the Vec.
+    pub fn commit(self) {
+        let CowVec { main, .. } = self.cowvec;
+        if let CowVecContent::Owned(vec) = main.content {
+            // Safe since the CowVec, which was the only thing borrowing the Vec,
+            // has now been taken apart, and its contents are owned.
+            let source = unsafe { &mut *self.source };
+            // The length can't be changed through a CowVecMut, so the items are still
+            // at their original positions.
+            debug_assert_eq!(vec.len(), source.len());
+            debug_assert!(!main.modified.shifted());
+            for (index, item) in vec.into_iter().enumerate() {
+                if main.modified.contains(index) {
+                    source[index] = item;
+                }
+            }
+        }
+    }
+
+    /// Discard the changes, leaving the borrowed Vec untouched. This is the same as
+    /// dropping the CowVecMut.
+    pub fn rollback(self) {}
+}
+
+impl<'extvec, T: Clone + PartialEq> CowVecMut<'extvec, T> {
+    /// See [CowVec::diff](crate::CowVec::diff).
+    pub fn diff(&self) -> Patch<T> {
+        self.cowvec.diff()
+    }
+}
+
+impl<'extvec, T: Clone> Deref for CowVecMut<'extvec, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        &self.cowvec
+    }
+}
+
+impl<'extvec, T: Clone> DerefMut for CowVecMut<'extvec, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.cowvec
+    }
+}
+
+impl<'extvec, T: Clone, I: SliceIndex<[T]>> Index<I> for CowVecMut<'extvec, T> {
+    type Output = I::Output;
+    fn index(&self, index: I) -> &I::Output {
+        &self.cowvec[index]
+    }
+}
+
+impl<'extvec, T: Clone, I: SliceIndex<[T]>> IndexMut<I> for CowVecMut<'extvec, T> {
+    fn index_mut(&mut self, index: I) -> &mut I::Output {
+        &mut self.cowvec[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CowVec;
+
+    #[test]
+    fn test_commit_copies_modified_items() {
+        let mut v: Vec<i32> = (0..10).collect();
+        let ptr = v.as_ptr();
+        let mut temp = CowVec::from_mut(&mut v);
+        temp.fast_for_each_mut(|item| {
+            if **item % 4 == 0 {
+                **item += 100;
+            }
+        });
+        temp[9] = 90;
+        assert_eq!(
+            temp.modified_indices().collect::<Vec<_>>(),
+            vec![0, 4, 8, 9]
+        );
+        temp.commit();
+        assert_eq!(v, vec![100, 1, 2, 3, 104, 5, 6, 7, 108, 90]);
+        assert_eq!(v.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut v = vec![1i32, 2, 3];
+        let mut temp = CowVec::from_mut(&mut v);
+        for mut item in temp.iter_mut() {
+            *item = 0;
+        }
+        assert!(temp.is_owned());
+        temp.rollback();
+        assert_eq!(v, vec![1, 2, 3]);
+
+        let mut temp = CowVec::from_mut(&mut v);
+        temp.fast_for_each_mut(|_item| {});
+        assert!(!temp.is_owned());
+        temp.commit();
+        assert_eq!(v, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_commit_after_reordering() {
+        let mut v = vec![3i32, 1, 2];
+        let mut temp = CowVec::from_mut(&mut v);
+        temp.sort();
+        temp.commit();
+        assert_eq!(v, vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,308 @@
+//! An undo stack of copy-on-write generations.
+
+use crate::{CowVec, FastForeachItem};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// The contents of one generation. Generations which have not been changed share the
+/// contents of the previous one, so they only cost a pointer.
+enum Generation<'extvec, T> {
+    Borrowed(&'extvec [T]),
+    Owned(Arc<Vec<T>>),
+}
+
+impl<'extvec, T> Generation<'extvec, T> {
+    fn share(&self) -> Generation<'extvec, T> {
+        match self {
+            Generation::Borrowed(v) => Generation::Borrowed(v),
+            Generation::Owned(v) => Generation::Owned(v.clone()),
+        }
+    }
+    fn as_slice(&self) -> &[T] {
+        match self {
+            Generation::Borrowed(v) => v,
+            Generation::Owned(v) => v,
+        }
+    }
+    fn shares_with(&self, other: &Generation<'extvec, T>) -> bool {
+        match (self, other) {
+            (Generation::Borrowed(a), Generation::Borrowed(b)) => std::ptr::eq(*a, *b),
+            (Generation::Owned(a), Generation::Owned(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Puts the contents edited in place back into their generation when dropped, so that
+/// they are not lost if the closure given to edit panics.
+struct EditGuard<'a, T: Clone> {
+    contents: &'a mut Vec<T>,
+    cowvec: CowVec<'a, T>,
+}
+
+impl<'a, T: Clone> Drop for EditGuard<'a, T> {
+    fn drop(&mut self) {
+        let cowvec = std::mem::replace(&mut self.cowvec, CowVec::from_owned(Vec::new()));
+        *self.contents = cowvec.into_owned();
+    }
+}
+
+/// A copy-on-write vector with a stack of generations, for editor-style undo and redo.
+///
+/// The first generation borrows the initial Vec. Each call to snapshot starts a new
+/// generation, which shares the contents of the previous one until it is written to. Just
+/// like [CowVec](crate::CowVec), a generation is only cloned when an item is actually
+/// mutated, so a generation without changes only costs a pointer.
+///
+/// undo and redo move between the generations. Making changes after undo discards the
+/// generations which could have been redone, and starts a new generation, so that the
+/// state undo returned to is kept.
+///
+/// ```
+/// # use cow_vec_item::CowVecHistory;
+/// let document = vec!["a", "b", "c"];
+/// let mut history = CowVecHistory::from(&document);
+/// history.edit(|v| v[1] = "B");
+/// history.snapshot();
+/// history.edit(|v| v.push("d"));
+/// assert_eq!(*history, ["a", "B", "c", "d"]);
+/// history.undo();
+/// assert_eq!(*history, ["a", "B", "c"]);
+/// history.redo();
+/// assert_eq!(*history, ["a", "B", "c", "d"]);
+/// ```
+pub struct CowVecHistory<'extvec, T> {
+    generations: Vec<Generation<'extvec, T>>,
+    // Index of the current generation
+    position: usize,
+}
+
+impl<'extvec, T: Clone> CowVecHistory<'extvec, T> {
+    /// Creates a CowVecHistory whose first generation borrows the given Vec.
+    #[allow(clippy::ptr_arg)]
+    pub fn from(vec: &'extvec Vec<T>) -> CowVecHistory<'extvec, T> {
+        CowVecHistory::from_slice(vec)
+    }
+    /// Creates a CowVecHistory whose first generation borrows the given slice.
+    pub fn from_slice(slice: &'extvec [T]) -> CowVecHistory<'extvec, T> {
+        CowVecHistory {
+            generations: vec![Generation::Borrowed(slice)],
+            position: 0,
+        }
+    }
+
+    /// Start a new generation, which shares the contents of the current one until it
+    /// is written to. If the current generation is not the last one (undo has been
+    /// called), the later generations are discarded.
+    pub fn snapshot(&mut self) {
+        self.generations.truncate(self.position + 1);
+        let shared = self.generations[self.position].share();
+        self.generations.push(shared);
+        self.position += 1;
+    }
+    /// Go back to the previous generation. Returns false if there is none.
+    pub fn undo(&mut self) -> bool {
+        if self.position == 0 {
+            return false;
+        }
+        self.position -= 1;
+        true
+    }
+    /// Go forward to the next generation, after undo. Returns false if there is none.
+    pub fn redo(&mut self) -> bool {
+        if self.position + 1 == self.generations.len() {
+            return false;
+        }
+        self.position += 1;
+        true
+    }
+    /// Returns true if undo would go back to a previous generation.
+    pub fn can_undo(&self) -> bool {
+        self.position > 0
+    }
+    /// Returns true if redo would go forward to a next generation.
+    pub fn can_redo(&self) -> bool {
+        self.position + 1 < self.generations.len()
+    }
+    /// The index of the current generation. The first generation is 0.
+    pub fn generation(&self) -> usize {
+        self.position
+    }
+    /// The number of generations, including those which can be redone.
+    pub fn generation_count(&self) -> usize {
+        self.generations.len()
+    }
+    /// The number of generations which have their own clone of the contents, rather
+    /// than sharing the contents of the previous generation.
+    pub fn owned_generation_count(&self) -> usize {
+        let mut count = 0;
+        let mut previous: Option<&Generation<'extvec, T>> = None;
+        for generation in self.generations.iter() {
+            if let Generation::Owned(_) = generation {
+                if !previous.is_some_and(|previous| previous.shares_with(generation)) {
+                    count += 1;
+                }
+            }
+            previous = Some(generation);
+        }
+        count
+    }
+
+    /// Change the current generation using the given closure, which is given a
+    /// [CowVec](crate::CowVec) borrowing the contents. The contents are only cloned if
+    /// the CowVec takes ownership, or if it no longer borrows all of the contents.
+    ///
+    /// If undo has been called, a new generation is started first, see
+    /// [snapshot](crate::CowVecHistory::snapshot).
+    pub fn edit<R, F>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut CowVec<'_, T>) -> R,
+    {
+        if self.position + 1 != self.generations.len() {
+            self.snapshot();
+        }
+        let current = &mut self.generations[self.position];
+        if let Generation::Owned(v) = current {
+            if let Some(v) = Arc::get_mut(v) {
+                // Not shared with any other generation, so it can be changed in place.
+                let cowvec = CowVec::from_owned(std::mem::take(v));
+                let mut guard = EditGuard {
+                    contents: v,
+                    cowvec,
+                };
+                return f(&mut guard.cowvec);
+            }
+        }
+        let contents = current.as_slice();
+        let mut cowvec = CowVec::from_slice(contents);
+        let result = f(&mut cowvec);
+        if cowvec.is_owned() || !std::ptr::eq(&*cowvec, contents) {
+            let changed = cowvec.into_owned();
+            *current = Generation::Owned(Arc::new(changed));
+        }
+        result
+    }
+
+    /// Like [CowVec::fast_for_each_mut](crate::CowVec::fast_for_each_mut), on the
+    /// current generation. See [edit](crate::CowVecHistory::edit).
+    pub fn fast_for_each_mut<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut dyn FastForeachItem<Target = T>),
+    {
+        self.edit(|cowvec| cowvec.fast_for_each_mut(f))
+    }
+
+    /// Returns the contents of the current generation as a Vec, cloning if needed.
+    pub fn into_owned(mut self) -> Vec<T> {
+        match self.generations.swap_remove(self.position) {
+            Generation::Borrowed(v) => v.to_vec(),
+            Generation::Owned(v) => Arc::try_unwrap(v).unwrap_or_else(|v| (*v).clone()),
+        }
+    }
+}
+
+impl<'extvec, T: Clone> Deref for CowVecHistory<'extvec, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        self.generations[self.position].as_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CowVecHistory;
+
+    #[test]
+    fn test_history_undo_redo() {
+        let v = vec![1i32, 2, 3];
+        let mut history = CowVecHistory::from(&v);
+        assert!(!history.undo());
+        history.edit(|v| v[0] = 10);
+        history.snapshot();
+        history.fast_for_each_mut(|item| {
+            if **item == 2 {
+                **item = 20;
+            }
+        });
+        history.snapshot();
+        history.edit(|v| v.pop());
+        assert_eq!(*history, [10, 20]);
+        assert_eq!(history.generation_count(), 3);
+
+        assert!(history.undo());
+        assert_eq!(*history, [10, 20, 3]);
+        assert!(history.undo());
+        assert_eq!(*history, [10, 2, 3]);
+        assert!(!history.undo());
+        assert!(history.redo());
+        assert!(history.redo());
+        assert!(!history.redo());
+        assert_eq!(*history, [10, 20]);
+        assert_eq!(v, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_history_unchanged_generations_are_shared() {
+        let v: Vec<i32> = (0..100).collect();
+        let mut history = CowVecHistory::from(&v);
+        history.snapshot();
+        history.fast_for_each_mut(|_item| {});
+        history.snapshot();
+        assert_eq!(history.owned_generation_count(), 0);
+
+        history.edit(|v| v[50] = 0);
+        history.snapshot();
+        history.snapshot();
+        history.edit(|v| v.iter().count());
+        assert_eq!(history.owned_generation_count(), 1);
+        // Further changes to a generation which is not shared don't clone again
+        history.undo();
+        history.undo();
+        history.redo();
+        history.redo();
+        history.edit(|v| v[1] = 0);
+        assert_eq!(history.owned_generation_count(), 2);
+        history.edit(|v| v[2] = 0);
+        assert_eq!(history.owned_generation_count(), 2);
+        assert_eq!(&history[..3], [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_history_edit_after_undo() {
+        let v = vec![1i32, 2, 3];
+        let mut history = CowVecHistory::from(&v);
+        history.snapshot();
+        history.edit(|v| v[0] = 10);
+        history.snapshot();
+        history.edit(|v| v[0] = 100);
+        history.undo();
+        history.undo();
+        assert_eq!(*history, [1, 2, 3]);
+
+        // Changing an earlier generation discards the later ones, but keeps the
+        // generation undo returned to.
+        history.edit(|v| v.truncate(1));
+        assert_eq!(*history, [1]);
+        assert_eq!(history.generation(), 1);
+        assert!(!history.can_redo());
+        assert!(history.undo());
+        assert_eq!(*history, [1, 2, 3]);
+        assert_eq!(history.into_owned(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_history_panicking_edit_keeps_contents() {
+        let v = vec![1i32, 2, 3];
+        let mut history = CowVecHistory::from(&v);
+        history.edit(|v| v[0] = 10);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            history.edit(|v| {
+                v[1] = 20;
+                panic!("edit failed");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(*history, [10, 20, 3]);
+        assert_eq!(v, vec![1, 2, 3]);
+    }
+}
@@ -9,8 +9,9 @@ This is the documentation for `cow_vec_item`.
 
 This is small wrapper crate which implements a copy-on-write version of Vec: [CowVec](crate::CowVec).
 
-This means CowVec is constructed from a reference to some shared Vec. The CowVec can then be used just
-as if it was a mutable Vec, but will copy the contents of the referenced Vec on demand, if needed.
+This means CowVec is constructed from a reference to some shared Vec (or any other slice, using
+[from_slice](crate::CowVec::from_slice)). The CowVec can then be used just as if it was a mutable Vec,
+but will copy the contents of the referenced Vec on demand, if needed.
 
 The extra value it brings over an std::borrow::Cow<Vec> is that it allows starting a mutable iteration
 over the wrapped Vec, but delaying cloning until an actual mutation occurs (or skipping
@@ -62,10 +63,13 @@ The Owned variant owns a Vec, whereas the Borrowed variant has a shared referenc
 some other Vec. The typical use case is to create an instance of CowVec borrowing another Vec,
 only taking ownership if necessary.
 
-CowVec implements both Deref and DerefMut, allowing access to all the standard methods on Vec.
+CowVec implements both Deref and DerefMut to a slice, allowing access to all the standard methods on slices.
+The to_mut method gives access to the owned Vec, and the most common Vec methods (push, pop, insert,
+remove, truncate, drain, split_off, ...) are also available directly on CowVec.
 
-Using DerefMut immediately ensures the contents are owned. For maximum efficiency, make sure not to use mutating
-methods unless needed.
+Using DerefMut or to_mut immediately ensures the contents are owned. For maximum efficiency, make sure not to use
+mutating methods unless needed. Removing items from the start or the end of a borrowing CowVec (truncate, pop,
+clear, split_off, and drain of a prefix or suffix) does not clone anything, the CowVec just borrows fewer items.
 
 To be able to iterate mutably without eagerly cloning the underlying Vec, a special iter_mut implementation is provided by CowVec.
 When using this method on CowVec, the returned values are not the actual contained items T, but rather a wrapper which
@@ -74,6 +78,38 @@ actually mutate the T, will the ownership be taken. (Ownership is also taken if
 write to the underlying T, CowVec does not detect this case so be sure to only obtain mutable references to T if you are
 actually going to write to them).
 
+Once ownership has been taken, CowVec keeps track of which items have been written, so that
+only these need to be processed further. See [modified_indices](crate::CowVec::modified_indices),
+[modified_ranges](crate::CowVec::modified_ranges) and [is_modified](crate::CowVec::is_modified).
+Writes through iter_mut, fast_for_each_mut, indexing and get_mut mark only the written items. DerefMut
+and to_mut mark all items, since the writes made through them can't be observed.
+
+To speculatively edit a Vec and then maybe write the changes back, use [from_mut](crate::CowVec::from_mut),
+which returns a [CowVecMut](crate::CowVecMut). Its commit method moves only the modified items back into the Vec.
+
+The borrowed Vec stays available after ownership has been taken, using [original](crate::CowVec::original),
+and changes can be undone using [revert](crate::CowVec::revert), [revert_range](crate::CowVec::revert_range)
+and [reset](crate::CowVec::reset). If a write has been undone, [try_reborrow](crate::CowVec::try_reborrow) drops
+an owned clone which is equal to the borrowed Vec, and [into_changed](crate::CowVec::into_changed) returns None
+if nothing has changed. [diff](crate::CowVec::diff) returns
+a [Patch](crate::Patch) describing the changes from the borrowed Vec to the current contents, which can
+be applied to other copies of the borrowed Vec.
+
+If items are only appended to a borrowed Vec, [CowVecAppend](crate::CowVecAppend) can be used
+instead. It keeps the borrowed items as a prefix, and stores appended items separately, so that the
+borrowed Vec is not cloned.
+
+For very large borrowed Vecs where only a few items are changed, [CowVecSparse](crate::CowVecSparse)
+records each changed item separately, instead of cloning the entire Vec. [CowVecChunked](crate::CowVecChunked)
+instead splits the Vec into fixed size chunks, and only clones the chunks which are written to.
+
+To keep many versions of a large vector, [CowVecPersistent](crate::CowVecPersistent) is a persistent
+vector which can be cloned in O(1), and where a mutation only copies the path to the changed item. Its
+leaves borrow the initial Vec until they are written to.
+
+For editor-style undo and redo, [CowVecHistory](crate::CowVecHistory) keeps a stack of generations. Each
+generation shares the contents of the previous one, and is only cloned when it is written to.
+
 # Multithreading
 
 CowVec is [Send](std::marker::Send) and [Sync](std::marker::Sync) if its contents are.
@@ -102,13 +138,33 @@ are always a possibility.
 */
 
 
+mod append;
+mod chunked;
+mod commit;
+mod history;
+mod modified;
+mod patch;
+mod persistent;
+mod sparse;
+pub use append::CowVecAppend;
+pub use chunked::CowVecChunked;
+pub use commit::CowVecMut;
+pub use history::CowVecHistory;
+pub use patch::{Patch, PatchOp};
+pub use persistent::CowVecPersistent;
+pub use sparse::{CowVecSparse, CowVecSparseItem, CowVecSparseIter};
+
+use std::iter::FusedIterator;
 use std::marker::PhantomData;
+use modified::ModifiedSet;
 use std::mem;
-use std::ops::{Deref, DerefMut};
+use std::ops::{Bound, ControlFlow, Deref, DerefMut, Index, IndexMut, Range, RangeBounds};
+use std::ptr::NonNull;
+use std::slice::SliceIndex;
 
 enum CowVecContent<'a, T> {
     Owned(Vec<T>),
-    Borrowed(&'a Vec<T>),
+    Borrowed(&'a [T]),
 }
 
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -120,6 +176,7 @@ enum WrapperState {
 /// An internal helper class
 pub struct CowVecMain<'extvec, T> {
     content: CowVecContent<'extvec, T>,
+    modified: ModifiedSet,
 
     // Iter
     item: *mut T,
@@ -136,10 +193,62 @@ pub struct BorrowedFastForeachItem<'extvec, T: Clone> {
 /// Internal helper struct. Concrete type of argument to user supplied closure in fast_for_each.
 pub struct OwnedForEachItem<T: Clone> {
     item: *mut T,
+    // Start of the owned Vec, and the set in which writes are recorded. Null if
+    // writes are not tracked.
+    start: *const T,
+    modified: *mut ModifiedSet,
 }
 /// Internal helper trait, argument to use supplied closure in fast_for_each
 pub trait FastForeachItem: Deref + DerefMut {}
 
+/// Return type of the closure given to try_fast_for_each_mut. Implemented for
+/// [ControlFlow<B>](std::ops::ControlFlow) and [Result<(), E>](std::result::Result).
+pub trait FastForeachControl {
+    /// The value returned if the iteration was not stopped early.
+    fn completed() -> Self;
+    /// Returns true if the iteration should stop, returning this value.
+    fn should_stop(&self) -> bool;
+}
+
+impl<B> FastForeachControl for ControlFlow<B> {
+    fn completed() -> Self {
+        ControlFlow::Continue(())
+    }
+    fn should_stop(&self) -> bool {
+        matches!(self, ControlFlow::Break(_))
+    }
+}
+
+impl<E> FastForeachControl for Result<(), E> {
+    fn completed() -> Self {
+        Ok(())
+    }
+    fn should_stop(&self) -> bool {
+        self.is_err()
+    }
+}
+
+/// Internal helper struct. Concrete type of argument to user supplied closure in
+/// retain_mut_lazy, while the contents are borrowed.
+pub struct BorrowedRetainItem<'extvec, T: Clone> {
+    item: &'extvec T,
+    copy: Option<T>,
+}
+
+impl<'extvec, T: Clone> Deref for BorrowedRetainItem<'extvec, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.copy.as_ref().unwrap_or(self.item)
+    }
+}
+impl<'extvec, T: Clone> DerefMut for BorrowedRetainItem<'extvec, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        let item = self.item;
+        self.copy.get_or_insert_with(|| item.clone())
+    }
+}
+impl<'extvec, T: Clone> FastForeachItem for BorrowedRetainItem<'extvec, T> {}
+
 impl<'extvec, T: Clone> Deref for BorrowedFastForeachItem<'extvec, T> {
     type Target = T;
     fn deref(&self) -> &T {
@@ -150,41 +259,95 @@ impl<'extvec, T: Clone> DerefMut for BorrowedFastForeachItem<'extvec, T> {
     fn deref_mut(&mut self) -> &mut T {
         let main = unsafe { &mut *self.main };
         if main.is_owned() {
+            main.mark_modified(self.item);
             unsafe { &mut *self.item }
         } else {
-            let index_offset_from_end_bytes = (self.end as usize).wrapping_sub(self.item as usize);
+            let (old_ptr, _) = main.content.mut_pointer();
+            let item_offset_bytes = (self.item as usize).wrapping_sub(old_ptr as usize);
+            let end_offset_bytes = (self.end as usize).wrapping_sub(old_ptr as usize);
             main.ensure_owned();
 
-            let (ptr, len) = main.content.mut_pointer();
-            self.end =
-                (ptr as *mut u8).wrapping_add(len * std::mem::size_of::<T>().max(1)) as *mut T;
-            self.item = (self.end as *mut u8).wrapping_sub(index_offset_from_end_bytes) as *mut T;
+            let (ptr, _len) = main.content.mut_pointer();
+            self.end = (ptr as *mut u8).wrapping_add(end_offset_bytes) as *mut T;
+            self.item = (ptr as *mut u8).wrapping_add(item_offset_bytes) as *mut T;
 
+            main.mark_modified(self.item);
             unsafe { &mut *self.item }
         }
     }
 }
 impl<'extvec, T: Clone> FastForeachItem for BorrowedFastForeachItem<'extvec, T> {}
 
-impl<'extvec, T: Clone> Deref for OwnedForEachItem<T> {
+impl<T: Clone> Deref for OwnedForEachItem<T> {
     type Target = T;
     fn deref(&self) -> &T {
         unsafe { &*self.item }
     }
 }
-impl<'extvec, T: Clone> DerefMut for OwnedForEachItem<T> {
+impl<T: Clone> DerefMut for OwnedForEachItem<T> {
     fn deref_mut(&mut self) -> &mut T {
+        if !self.modified.is_null() {
+            unsafe { &mut *self.modified }.insert(item_index(self.start, self.item));
+        }
         unsafe { &mut *self.item }
     }
 }
-impl<'extvec, T: Clone> FastForeachItem for OwnedForEachItem<T> {}
+impl<T: Clone> OwnedForEachItem<T> {
+    /// An item whose writes are not tracked.
+    fn untracked(item: *mut T) -> OwnedForEachItem<T> {
+        OwnedForEachItem {
+            item,
+            start: std::ptr::null(),
+            modified: std::ptr::null_mut(),
+        }
+    }
+}
+impl<T: Clone> FastForeachItem for OwnedForEachItem<T> {}
 
-/// A copy-on-write wrapper around a [Vec<T>](std::vec::Vec).
+/// A copy-on-write wrapper around a [Vec<T>](std::vec::Vec), or any borrowed slice.
 pub struct CowVec<'extvec, T> {
     main: CowVecMain<'extvec, T>,
+    // The slice the CowVec was created from. Kept after ownership has been taken,
+    // so that the changes can be determined. None if created using from_owned.
+    origin: Option<&'extvec [T]>,
+    // Set by set_auto_reborrow
+    auto_reborrow: bool,
     bad_wrapper_use_detector: WrapperState,
 }
 
+/// Convert a range into start and end indices, panicking if it is out of bounds for
+/// the given length.
+fn resolve_range<R: RangeBounds<usize>>(range: R, len: usize) -> (usize, usize) {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start
+            .checked_add(1)
+            .unwrap_or_else(|| panic!("cow_vec_item: range start overflows usize")),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end
+            .checked_add(1)
+            .unwrap_or_else(|| panic!("cow_vec_item: range end overflows usize")),
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+    if start > end {
+        panic!("cow_vec_item: range starts at {} but ends at {}", start, end);
+    }
+    if end > len {
+        panic!("cow_vec_item: range end {} out of range for CowVec of length {}", end, len);
+    }
+    (start, end)
+}
+
+/// The index of the item pointed to by item, in the slice starting at start.
+/// For zero sized types, the address is used as index.
+#[inline]
+fn item_index<T>(start: *const T, item: *const T) -> usize {
+    (item as usize).wrapping_sub(start as usize) / mem::size_of::<T>().max(1)
+}
+
 // The lifetime 'extvec is the lifetime of the borrowed external vector.
 impl<'extvec, T: Clone> CowVecContent<'extvec, T> {
     fn mut_pointer(&mut self) -> (*mut T, usize) {
@@ -194,6 +357,22 @@ impl<'extvec, T: Clone> CowVecContent<'extvec, T> {
         }
     }
 
+    /// Returns pointers to the first item in the range, and one past the last item.
+    /// For zero sized types, the address is used as index.
+    fn mut_pointer_range<R: RangeBounds<usize>>(&mut self, range: R) -> (*mut T, *mut T) {
+        let (ptr, len) = self.mut_pointer();
+        let (start, end) = resolve_range(range, len);
+        if mem::size_of::<T>() == 0 {
+            (
+                (ptr as *mut u8).wrapping_add(start) as *mut T,
+                (ptr as *mut u8).wrapping_add(end) as *mut T,
+            )
+        } else {
+            // Safety: Just pointer arithmetic, start and end have been checked against len.
+            unsafe { (ptr.add(start), ptr.add(end)) }
+        }
+    }
+
     fn ensure_owned(&mut self) {
         {
             if let CowVecContent::Owned(_) = self {
@@ -217,7 +396,6 @@ impl<'extvec, T: Clone> CowVecContent<'extvec, T> {
 /// function on [CowVecIter](crate::CowVecIter)
 pub struct CowVecItemWrapper<'extvec, 'cowvec, T> {
     item: *mut T,
-    end: *mut T,
     cowvec: *mut CowVecMain<'extvec, T>,
     owned: bool,
     bad_wrapper_use_detector: *mut WrapperState,
@@ -233,23 +411,61 @@ impl<'extvec, 'cowvec, T> Drop for CowVecItemWrapper<'extvec, 'cowvec, T> {
     }
 }
 impl<'extvec, T: Clone> Deref for CowVec<'extvec, T> {
-    type Target = Vec<T>;
+    type Target = [T];
 
     fn deref(&self) -> &Self::Target {
         match &self.main.content {
             CowVecContent::Owned(v) => v,
-            CowVecContent::Borrowed(v) => *v,
+            CowVecContent::Borrowed(v) => v,
         }
     }
 }
 
 impl<'extvec, T: Clone> DerefMut for CowVec<'extvec, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.main.content.ensure_owned();
-        match &mut self.main.content {
-            CowVecContent::Owned(v) => v,
-            _ => unreachable!(),
-        }
+        let (v, modified) = self.main.owned_parts();
+        modified.insert_all();
+        v
+    }
+}
+
+impl<'extvec, T: Clone> Extend<T> for CowVec<'extvec, T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let (v, modified) = self.main.owned_parts();
+        let start = v.len();
+        v.extend(iter);
+        modified.insert_range(start, v.len());
+    }
+}
+
+impl<'extvec, T: Clone, I: SliceIndex<[T]>> Index<I> for CowVec<'extvec, T> {
+    type Output = I::Output;
+    fn index(&self, index: I) -> &I::Output {
+        &self.deref()[index]
+    }
+}
+
+impl<'extvec, T: Clone, I: SliceIndex<[T]>> IndexMut<I> for CowVec<'extvec, T> {
+    /// Take ownership, and mark the indexed items as modified.
+    fn index_mut(&mut self, index: I) -> &mut I::Output {
+        let (v, modified) = self.main.owned_parts();
+        let start = v.as_ptr();
+        let output = &mut v[index];
+        mark_modified_output(modified, start, output);
+        output
+    }
+}
+
+/// Mark the items referenced by output (an item or a sub-slice of the slice
+/// starting at start) as modified.
+fn mark_modified_output<T, O: ?Sized>(modified: &mut ModifiedSet, start: *const T, output: &O) {
+    if mem::size_of::<T>() == 0 {
+        // All items of a zero sized type have the same address, so we can't tell which
+        // ones are referenced.
+        modified.insert_all();
+    } else {
+        let first = item_index(start, output as *const O as *const T);
+        modified.insert_range(first, first + mem::size_of_val(output) / mem::size_of::<T>());
     }
 }
 
@@ -267,64 +483,124 @@ impl<'extvec, 'cowvec, T: Clone> Deref for CowVecItemWrapper<'extvec, 'cowvec, T
 impl<'extvec, 'cowvec, T: Clone> DerefMut for CowVecItemWrapper<'extvec, 'cowvec, T> {
     #[inline]
     fn deref_mut(&mut self) -> &mut Self::Target {
-        if self.owned {
-            // Safe because we know that CowVec must still be alive since
-            // the lifetime of originating CowVec is known to outlive the values
-            // returned from the iterator.
-            unsafe { &mut *self.item }
-        } else {
-            let index_offset_from_end_bytes;
-            {
-                index_offset_from_end_bytes = (self.end as usize).wrapping_sub(self.item as usize);
-            }
+        // Safe because we know that CowVec must still be alive since
+        // the lifetime of originating CowVec is known to outlive the values
+        // returned from the iterator.
+        let self_parent = unsafe { &mut *self.cowvec };
+        if !self.owned {
+            debug_assert!(!self_parent.is_owned());
+            self.item = self_parent.ensure_owned_rebase(self.item);
+            self.owned = true;
+        }
+        self_parent.mark_modified(self.item);
 
-            // Safe because we know that CowVec must still be alive since
-            // the lifetime of originating CowVec is known to outlive the values
-            // returned from the iterator.
-            let self_parent = unsafe { &mut *self.cowvec };
+        // Safe since the originating CowVec and both possible referenced slices
+        // (owned or borrowed) must still be alive.
+        unsafe { &mut *self.item }
+    }
+}
+/// A value being iterated over by [CowVecLendingIter](crate::CowVecLendingIter).
+///
+/// Unlike [CowVecItemWrapper](crate::CowVecItemWrapper), the item borrows the iterator
+/// mutably, so the borrow checker ensures it has been dropped before the next item is
+/// requested.
+pub struct CowVecLendingItem<'extvec, 'iter, T> {
+    item: *mut T,
+    cowvec: *mut CowVecMain<'extvec, T>,
+    phantom: PhantomData<&'iter mut ()>,
+}
 
-            debug_assert_eq!(self_parent.is_owned(), false);
-            self_parent.ensure_owned();
-            {
-                let (ptr, len) = self_parent.content.mut_pointer();
+impl<'extvec, 'iter, T: Clone> Deref for CowVecLendingItem<'extvec, 'iter, T> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        // Safe because the item mutably borrows the iterator, which in turn
+        // mutably borrows the CowVec.
+        unsafe { &*self.item }
+    }
+}
 
-                let old_index_offset_from_end =
-                    index_offset_from_end_bytes / (std::mem::size_of::<T>().max(1)); // Does a better way exist on stable?
+impl<'extvec, 'iter, T: Clone> DerefMut for CowVecLendingItem<'extvec, 'iter, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safe because the item mutably borrows the iterator, which in turn
+        // mutably borrows the CowVec. No other item can be alive, so if the CowVec is
+        // owned, self.item already points into the owned Vec.
+        let main = unsafe { &mut *self.cowvec };
+        if !main.is_owned() {
+            self.item = main.ensure_owned_rebase(self.item);
+        }
+        main.mark_modified(self.item);
+        unsafe { &mut *self.item }
+    }
+}
 
-                // The following unsafe pointer arithmetic is safe since we know the slice
-                // operated on is still alive (either owned or borrowed), and there can be
-                // no over- or underflow since the slice is borrowed and thus its length and
-                // address is immutable.
-                let item = if mem::size_of::<T>() == 0 {
-                    (ptr as *mut u8).wrapping_add(len - old_index_offset_from_end) as *mut T
-                } else {
-                    unsafe { ptr.add(len - old_index_offset_from_end) }
-                };
+/// A placeholder representing a chunk of items being iterated over - the return value of
+/// the next() function on [CowVecChunksIter](crate::CowVecChunksIter).
+///
+/// Dereferences to a slice. The CowVec takes ownership only when the chunk is
+/// accessed mutably.
+pub struct CowVecChunkWrapper<'extvec, 'cowvec, T> {
+    item: *mut T,
+    len: usize,
+    cowvec: *mut CowVecMain<'extvec, T>,
+    owned: bool,
+    // Set once the chunk has been recorded as modified, so that it is only done once.
+    marked: bool,
+    bad_wrapper_use_detector: *mut WrapperState,
+    phantom: PhantomData<&'cowvec mut ()>,
+}
 
-                let end = if mem::size_of::<T>() == 0 {
-                    (ptr as *mut u8).wrapping_add(len) as *mut T
-                } else {
-                    unsafe { ptr.add(len) }
-                };
+impl<'extvec, 'cowvec, T> Drop for CowVecChunkWrapper<'extvec, 'cowvec, T> {
+    fn drop(&mut self) {
+        // Safe since the originating CowVec must still be alive because of
+        // lifetime constraints of CowVecChunkWrapper.
+        *unsafe { &mut *self.bad_wrapper_use_detector } = WrapperState::Dead;
+    }
+}
 
-                let parent_item = if mem::size_of::<T>() == 0 {
-                    (ptr as *mut u8).wrapping_add(len - old_index_offset_from_end + 1) as *mut T
-                } else {
-                    unsafe { ptr.add(len - old_index_offset_from_end + 1) }
-                };
+impl<'extvec, 'cowvec, T: Clone> Deref for CowVecChunkWrapper<'extvec, 'cowvec, T> {
+    type Target = [T];
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        if mem::size_of::<T>() == 0 {
+            // For zero sized types the item pointer is just an index, not a valid address.
+            return unsafe { std::slice::from_raw_parts(NonNull::dangling().as_ptr(), self.len) };
+        }
+        // Safe because we know that CowVec must still be alive since
+        // the lifetime of originating CowVec is known to outlive the values
+        // returned from the iterator.
+        unsafe { std::slice::from_raw_parts(self.item, self.len) }
+    }
+}
 
-                self_parent.item = parent_item;
-                self_parent.end = end;
-                self.owned = true;
-                self.item = item;
-                self.end = end;
-            }
-            // Safe since the originating CowVec and both possible referenced slices
-            // (owned or borrowed) must still be alive.
-            unsafe { &mut *self.item }
+impl<'extvec, 'cowvec, T: Clone> DerefMut for CowVecChunkWrapper<'extvec, 'cowvec, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safe because we know that CowVec must still be alive since
+        // the lifetime of originating CowVec is known to outlive the values
+        // returned from the iterator.
+        let self_parent = unsafe { &mut *self.cowvec };
+        if !self.owned {
+            debug_assert!(!self_parent.is_owned());
+            self.item = self_parent.ensure_owned_rebase(self.item);
+            self.owned = true;
+        }
+        if !self.marked {
+            self_parent.mark_modified_range(self.item, self.len);
+            self.marked = true;
         }
+        if mem::size_of::<T>() == 0 {
+            return unsafe {
+                std::slice::from_raw_parts_mut(NonNull::dangling().as_ptr(), self.len)
+            };
+        }
+        // Safe since the originating CowVec and both possible referenced slices
+        // (owned or borrowed) must still be alive.
+        unsafe { std::slice::from_raw_parts_mut(self.item, self.len) }
     }
 }
+
 impl<'extvec, T: Clone> CowVecMain<'extvec, T> {
     #[inline]
     fn is_owned(&self) -> bool {
@@ -336,6 +612,52 @@ impl<'extvec, T: Clone> CowVecMain<'extvec, T> {
     fn ensure_owned(&mut self) {
         self.content.ensure_owned();
     }
+
+    /// Take ownership without marking anything as modified, and return the owned Vec
+    /// along with the set of modified indices.
+    fn owned_parts(&mut self) -> (&mut Vec<T>, &mut ModifiedSet) {
+        self.content.ensure_owned();
+        match &mut self.content {
+            CowVecContent::Owned(v) => (v, &mut self.modified),
+            CowVecContent::Borrowed(_) => unreachable!(),
+        }
+    }
+
+    /// Record that the item pointed to by item, which must point into the owned Vec,
+    /// has been written.
+    #[inline]
+    fn mark_modified(&mut self, item: *mut T) {
+        let (ptr, _) = self.content.mut_pointer();
+        self.modified.insert(item_index(ptr, item));
+    }
+    /// Like mark_modified, but for len items starting at item.
+    fn mark_modified_range(&mut self, item: *mut T, len: usize) {
+        let (ptr, _) = self.content.mut_pointer();
+        let start = item_index(ptr, item);
+        self.modified.insert_range(start, start + len);
+    }
+
+    /// Take ownership, moving both iteration cursors as well as the given item pointer
+    /// from the borrowed slice to the owned clone. Returns the moved item pointer.
+    fn ensure_owned_rebase(&mut self, item: *mut T) -> *mut T {
+        // The pointers are recorded as byte offsets from the start of the borrowed slice.
+        // This works for zero sized types as well, since we then use the address as an index.
+        let (old_ptr, _) = self.content.mut_pointer();
+        let item_offset_bytes = (item as usize).wrapping_sub(old_ptr as usize);
+        let cursor_item_offset_bytes = (self.item as usize).wrapping_sub(old_ptr as usize);
+        let cursor_end_offset_bytes = (self.end as usize).wrapping_sub(old_ptr as usize);
+
+        self.ensure_owned();
+
+        let (ptr, _len) = self.content.mut_pointer();
+
+        // The following pointer arithmetic is safe since we know the slice
+        // operated on is still alive (either owned or borrowed), and the owned
+        // clone has exactly the same length as the borrowed slice.
+        self.item = (ptr as *mut u8).wrapping_add(cursor_item_offset_bytes) as *mut T;
+        self.end = (ptr as *mut u8).wrapping_add(cursor_end_offset_bytes) as *mut T;
+        (ptr as *mut u8).wrapping_add(item_offset_bytes) as *mut T
+    }
 }
 
 impl<'extvec, T: Clone> CowVec<'extvec, T> {
@@ -366,9 +688,12 @@ impl<'extvec, T: Clone> CowVec<'extvec, T> {
         CowVec {
             main: CowVecMain {
                 content: CowVecContent::Owned(vec),
+                modified: ModifiedSet::new(),
                 item: std::ptr::null_mut(),
                 end: std::ptr::null_mut(),
             },
+            origin: None,
+            auto_reborrow: false,
             bad_wrapper_use_detector: WrapperState::Dead,
         }
     }
@@ -377,16 +702,201 @@ impl<'extvec, T: Clone> CowVec<'extvec, T> {
     /// to the clone instead.
     #[allow(clippy::ptr_arg)]
     pub fn from(vec: &'extvec Vec<T>) -> CowVec<'extvec, T> {
+        CowVec::from_slice(vec)
+    }
+    /// Creates a CowVec which borrows the given slice. This works just like from,
+    /// but allows borrowing arrays, sub-slices, boxed slices and so on, without
+    /// first copying them into a Vec. The first time the CowVec is mutated,
+    /// the slice is cloned into a Vec.
+    pub fn from_slice(slice: &'extvec [T]) -> CowVec<'extvec, T> {
         CowVec {
             main: CowVecMain {
-                content: CowVecContent::Borrowed(vec),
+                content: CowVecContent::Borrowed(slice),
+                modified: ModifiedSet::new(),
                 item: std::ptr::null_mut(),
                 end: std::ptr::null_mut(),
             },
+            origin: Some(slice),
+            auto_reborrow: false,
             bad_wrapper_use_detector: WrapperState::Dead,
         }
     }
 
+    /// Creates a [CowVecMut](crate::CowVecMut), which borrows the given Vec mutably. It
+    /// works like a CowVec created using from, but the changes can be written back into
+    /// the Vec using commit, which only copies the modified items.
+    pub fn from_mut(vec: &'extvec mut Vec<T>) -> CowVecMut<'extvec, T> {
+        CowVecMut::new(vec)
+    }
+
+    /// Take ownership, and return a mutable reference to the owned Vec.
+    /// This gives access to all the mutating methods of Vec.
+    ///
+    /// Since the writes made through the returned Vec can't be tracked, all items
+    /// are marked as modified.
+    pub fn to_mut(&mut self) -> &mut Vec<T> {
+        let (v, modified) = self.main.owned_parts();
+        modified.insert_all();
+        modified.set_shifted();
+        v
+    }
+
+    /// Returns a mutable reference to an item or a sub-slice, or None if out of bounds.
+    /// This takes ownership, and marks only the referenced items as modified. If the
+    /// index is out of bounds, ownership is not taken.
+    pub fn get_mut<I: SliceIndex<[T]> + Clone>(&mut self, index: I) -> Option<&mut I::Output> {
+        self.get(index.clone())?;
+        let (v, modified) = self.main.owned_parts();
+        let start = v.as_ptr();
+        let output = v.get_mut(index)?;
+        mark_modified_output(modified, start, output);
+        Some(output)
+    }
+
+    /// Appends an item to the back of the CowVec, taking ownership.
+    pub fn push(&mut self, value: T) {
+        let (v, modified) = self.main.owned_parts();
+        modified.insert(v.len());
+        v.push(value);
+    }
+
+    /// Inserts an item at the given index, taking ownership.
+    ///
+    /// Panics if index > len.
+    pub fn insert(&mut self, index: usize, value: T) {
+        let (v, modified) = self.main.owned_parts();
+        v.insert(index, value);
+        modified.splice(index, 0, 1);
+        modified.insert(index);
+    }
+
+    /// Removes and returns the item at the given index, taking ownership.
+    ///
+    /// Panics if index is out of bounds.
+    pub fn remove(&mut self, index: usize) -> T {
+        let (v, modified) = self.main.owned_parts();
+        let removed = v.remove(index);
+        modified.splice(index, 1, 0);
+        removed
+    }
+
+    /// Removes the last item and returns it, or None if the CowVec is empty.
+    /// If the contents are borrowed, only the removed item is cloned and the CowVec
+    /// keeps borrowing the remaining items.
+    pub fn pop(&mut self) -> Option<T> {
+        match &mut self.main.content {
+            CowVecContent::Owned(v) => {
+                let last = v.pop();
+                self.main.modified.truncate(v.len());
+                last
+            }
+            CowVecContent::Borrowed(v) => {
+                let (last, rest) = v.split_last()?;
+                *v = rest;
+                Some(last.clone())
+            }
+        }
+    }
+
+    /// Shortens the CowVec to the given length. Does nothing if len is
+    /// greater than the current length.
+    /// If the contents are borrowed, this does not clone anything.
+    pub fn truncate(&mut self, len: usize) {
+        match &mut self.main.content {
+            CowVecContent::Owned(v) => {
+                v.truncate(len);
+                self.main.modified.truncate(len);
+            }
+            CowVecContent::Borrowed(v) => {
+                if len < v.len() {
+                    *v = &v[..len];
+                }
+            }
+        }
+    }
+
+    /// Removes all items. If the contents are borrowed, this does not clone anything.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Removes the given range of items, returning them as an iterator.
+    ///
+    /// If the contents are borrowed and the range is empty, a prefix or a suffix, the
+    /// CowVec keeps borrowing the remaining items, and the removed items are only cloned
+    /// as the returned iterator reaches them. In that case the items are removed
+    /// immediately, even if the returned iterator is leaked.
+    ///
+    /// Otherwise, ownership is taken, and this works like Vec::drain. If the returned
+    /// iterator is leaked, the items after the range may be lost as well, and
+    /// [is_modified](crate::CowVec::is_modified) may no longer match the items.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn drain<R: RangeBounds<usize>>(&mut self, range: R) -> CowVecDrain<'_, T> {
+        let (start, end) = resolve_range(range, self.len());
+        if let CowVecContent::Borrowed(v) = &mut self.main.content {
+            if start == end {
+                return CowVecDrain {
+                    inner: CowVecDrainInner::Borrowed([].iter()),
+                };
+            }
+            if start == 0 || end == v.len() {
+                if end != v.len() {
+                    // The remaining items no longer start at index 0 of the origin
+                    self.main.modified.set_shifted();
+                }
+                let drained = &v[start..end];
+                *v = if start == 0 { &v[end..] } else { &v[..start] };
+                return CowVecDrain {
+                    inner: CowVecDrainInner::Borrowed(drained.iter()),
+                };
+            }
+        }
+        let (v, modified) = self.main.owned_parts();
+        modified.splice(start, end - start, 0);
+        CowVecDrain {
+            inner: CowVecDrainInner::Owned(v.drain(start..end)),
+        }
+    }
+
+    /// Splits the CowVec in two at the given index. Returns a CowVec with the items from
+    /// index 'at' to the end, and keeps the items before 'at'.
+    ///
+    /// If the contents are borrowed, both halves keep borrowing the initial Vec,
+    /// and nothing is cloned.
+    ///
+    /// The original of the returned CowVec (see [original](crate::CowVec::original)) is
+    /// the part of the original from index 'at' onwards, so that diff and reset work on
+    /// both halves. If items have been inserted or removed before 'at' since ownership
+    /// was taken, that part can't be determined, and the returned CowVec behaves as if
+    /// created using from_owned.
+    ///
+    /// Panics if at > len.
+    pub fn split_off(&mut self, at: usize) -> CowVec<'extvec, T> {
+        match &mut self.main.content {
+            CowVecContent::Owned(v) => {
+                let mut tail = CowVec::from_owned(v.split_off(at));
+                if !self.main.modified.shifted() {
+                    tail.origin = self.origin.map(|origin| &origin[at.min(origin.len())..]);
+                }
+                tail.main.modified = self.main.modified.split_off(at);
+                tail
+            }
+            CowVecContent::Borrowed(v) => {
+                if at > v.len() {
+                    panic!(
+                        "cow_vec_item: split_off index {} out of range for CowVec of length {}",
+                        at,
+                        v.len()
+                    );
+                }
+                let (head, tail) = v.split_at(at);
+                *v = head;
+                CowVec::from_slice(tail)
+            }
+        }
+    }
+
     /// An optimized for_each for CowVec. This has approximately half the overhead
     /// of iter().for_each(), because it takes advantage of the reduced safety mechanisms
     /// needed when doing internal iteration.
@@ -394,23 +904,28 @@ impl<'extvec, T: Clone> CowVec<'extvec, T> {
     /// The only use visible difference is that the user supplied closure is given a
     /// an object which appears to be a reference to a reference to an object, meaning
     /// you may have to use **item to access it instead of *item.
-    pub fn fast_for_each_mut<F>(&mut self, mut f: F)
+    pub fn fast_for_each_mut<F>(&mut self, f: F)
     where
         F: FnMut(&mut dyn FastForeachItem<Target = T>),
     {
-        let (ptr, len) = self.main.content.mut_pointer();
-        let end = if mem::size_of::<T>() == 0 {
-            (ptr as *mut u8).wrapping_add(self.len()) as *mut T
-        } else {
-            // Safety: Just pointer arithmetic. Slice address and length are immutable because of lifetimes.
-            unsafe { ptr.add(len) }
-        };
+        self.fast_for_each_mut_range(.., f)
+    }
+
+    /// Like fast_for_each_mut, but only visits the items within the given range.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn fast_for_each_mut_range<R, F>(&mut self, range: R, mut f: F)
+    where
+        R: RangeBounds<usize>,
+        F: FnMut(&mut dyn FastForeachItem<Target = T>),
+    {
+        let (ptr, end) = self.main.content.mut_pointer_range(range);
 
         if !self.main.is_owned() {
             let mut state = BorrowedFastForeachItem {
                 main: &mut self.main,
                 item: ptr,
-                end: end,
+                end,
             };
 
             while state.item != state.end {
@@ -423,7 +938,11 @@ impl<'extvec, T: Clone> CowVec<'extvec, T> {
 
             }
         } else {
-            let mut state = OwnedForEachItem { item: ptr };
+            let mut state = OwnedForEachItem {
+                item: ptr,
+                start: self.main.content.mut_pointer().0,
+                modified: &mut self.main.modified,
+            };
             while state.item != end {
                 f(&mut state);
                 if mem::size_of::<T>() == 0 {
@@ -435,396 +954,2529 @@ impl<'extvec, T: Clone> CowVec<'extvec, T> {
         }
     }
 
-    /// Iterate mutable over the CowVec, returning wrapped values which
-    /// implement DerefMut. If the returned wrapped value is accessed mutably, and not
-    /// only read, the CowVec will clone its contents and take ownership of the clone.
+    /// Like fast_for_each_mut, but stops as soon as the closure returns
+    /// [ControlFlow::Break](std::ops::ControlFlow::Break) or an
+    /// [Err](std::result::Result::Err), and returns that value.
     ///
-    /// If you don't need an iterator, but just need to traverse all values,
-    /// it is much faster to use the fast_for_each_mut() method instead.
-    pub fn iter_mut<'cowvec>(&'cowvec mut self) -> CowVecIter<'extvec, 'cowvec, T> {
-        if self.bad_wrapper_use_detector != WrapperState::Dead {
-            unreachable!("cow_vec_item: iter_mut was called while wrappers from a previous iter_mut were still alive! I had expected rust ownership rules to make this impossible. Please file a bug!");
-        }
-
-        let (ptr, len) = self.main.content.mut_pointer();
-        let end = if mem::size_of::<T>() == 0 {
-            (ptr as *mut u8).wrapping_add(self.len()) as *mut T
-        } else {
-            // Safety: Just pointer arithmetic. Slice address and length are immutable because of lifetimes.
-            unsafe { ptr.add(len) }
-        };
+    /// If no item was mutated before the iteration stopped, the CowVec still
+    /// borrows the initial Vec.
+    pub fn try_fast_for_each_mut<R, F>(&mut self, mut f: F) -> R
+    where
+        R: FastForeachControl,
+        F: FnMut(&mut dyn FastForeachItem<Target = T>) -> R,
+    {
+        let (ptr, end) = self.main.content.mut_pointer_range(..);
 
-        self.main.item = ptr;
-        self.main.end = end;
+        if !self.main.is_owned() {
+            let mut state = BorrowedFastForeachItem {
+                main: &mut self.main,
+                item: ptr,
+                end,
+            };
 
-        CowVecIter {
-            cowvec: &mut self.main as *mut CowVecMain<T>,
-            bad_wrapper_use_detector: &mut self.bad_wrapper_use_detector as *mut WrapperState,
-            phantom: PhantomData,
+            while state.item != state.end {
+                let result = f(&mut state);
+                if result.should_stop() {
+                    return result;
+                }
+                if mem::size_of::<T>() == 0 {
+                    state.item = (state.item as *mut u8).wrapping_add(1) as *mut T;
+                } else {
+                    state.item = state.item.wrapping_add(1);
+                }
+            }
+        } else {
+            let mut state = OwnedForEachItem {
+                item: ptr,
+                start: self.main.content.mut_pointer().0,
+                modified: &mut self.main.modified,
+            };
+            while state.item != end {
+                let result = f(&mut state);
+                if result.should_stop() {
+                    return result;
+                }
+                if mem::size_of::<T>() == 0 {
+                    state.item = (state.item as *mut u8).wrapping_add(1) as *mut T;
+                } else {
+                    state.item = state.item.wrapping_add(1);
+                }
+            }
         }
+        R::completed()
     }
 
-    /// Iterate mutably over the CowVec, returning mutable references.
-    /// This method immediately, eagerly, takes ownership of the wrapped
-    /// Vec (cloning if necessary).
-    /// In most cases what you want is the iter_mut method, which can avoid taking
-    /// ownership unless necessary. This method can be useful though, since the
-    /// reduced book-keeping makes it run significantly faster.
-    pub fn eager_cloned_iter_mut<'cowvec>(&'cowvec mut self) -> impl Iterator<Item = &mut T>
-    where
-        'extvec: 'cowvec,
+    /// Like fast_for_each_mut, but iterates over this CowVec and another one in lockstep,
+    /// giving the closure one item from each. Each CowVec takes ownership independently,
+    /// only if its own items are mutated.
+    ///
+    /// Panics if the two CowVecs have different lengths.
+    pub fn zip_fast_for_each_mut<'othervec, U, F>(
+        &mut self,
+        other: &mut CowVec<'othervec, U>,
+        mut f: F,
+    ) where
+        U: Clone,
+        F: FnMut(&mut dyn FastForeachItem<Target = T>, &mut dyn FastForeachItem<Target = U>),
     {
-        self.main.content.ensure_owned();
-        match &mut self.main.content {
-            CowVecContent::Owned(v) => v.iter_mut(),
-            CowVecContent::Borrowed(_) => unreachable!(),
+        if self.len() != other.len() {
+            panic!(
+                "cow_vec_item: zip_fast_for_each_mut called with CowVecs of different lengths ({} and {})",
+                self.len(),
+                other.len()
+            );
         }
-    }
-}
+        let (ptr, end) = self.main.content.mut_pointer_range(..);
+        let (other_ptr, other_end) = other.main.content.mut_pointer_range(..);
+
+        // BorrowedFastForeachItem works for owned contents too, it just checks ownership
+        // on each mutable access. This avoids needing a loop for each combination.
+        let mut state = BorrowedFastForeachItem {
+            main: &mut self.main,
+            item: ptr,
+            end,
+        };
+        let mut other_state = BorrowedFastForeachItem {
+            main: &mut other.main,
+            item: other_ptr,
+            end: other_end,
+        };
 
-/// Mutable smart iterator over a CowVec. This is an internal
-/// detail that shouldn't be used directly.
-pub struct CowVecIter<'extvec, 'cowvec, T> {
-    // The lifetime 'cowvec is the lifetime of CowVec object itself
-    cowvec: *mut CowVecMain<'extvec, T>,
-    bad_wrapper_use_detector: *mut WrapperState,
-    phantom: PhantomData<&'cowvec mut ()>,
-}
+        while state.item != state.end {
+            f(&mut state, &mut other_state);
+            if mem::size_of::<T>() == 0 {
+                state.item = (state.item as *mut u8).wrapping_add(1) as *mut T;
+            } else {
+                state.item = state.item.wrapping_add(1);
+            }
+            if mem::size_of::<U>() == 0 {
+                other_state.item = (other_state.item as *mut u8).wrapping_add(1) as *mut U;
+            } else {
+                other_state.item = other_state.item.wrapping_add(1);
+            }
+        }
+    }
 
-impl<'extvec, 'cowvec, T: Clone> Iterator for CowVecIter<'extvec, 'cowvec, T>
-where
-    'extvec: 'cowvec,
-{
-    type Item = CowVecItemWrapper<'extvec, 'cowvec, T>;
+    /// Like fast_for_each_mut, but the closure is also given the index of each item.
+    /// This is much faster than using iter_mut().enumerate().
+    pub fn fast_for_each_mut_indexed<F>(&mut self, mut f: F)
+    where
+        F: FnMut(usize, &mut dyn FastForeachItem<Target = T>),
+    {
+        let (ptr, end) = self.main.content.mut_pointer_range(..);
+        let mut index = 0;
+        if !self.main.is_owned() {
+            let mut state = BorrowedFastForeachItem {
+                main: &mut self.main,
+                item: ptr,
+                end,
+            };
 
-    fn count(self) -> usize {
-        let theref = unsafe { &*self.cowvec };
-        (theref.end as usize - theref.item as usize) / (std::mem::size_of::<T>().max(1))
-    }
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        let theref = unsafe { &*self.cowvec };
-        let size = (theref.end as usize - theref.item as usize) / (std::mem::size_of::<T>().max(1));
-        (size, Some(size))
+            while state.item != state.end {
+                f(index, &mut state);
+                index += 1;
+                if mem::size_of::<T>() == 0 {
+                    state.item = (state.item as *mut u8).wrapping_add(1) as *mut T;
+                } else {
+                    state.item = state.item.wrapping_add(1);
+                }
+            }
+        } else {
+            let mut state = OwnedForEachItem {
+                item: ptr,
+                start: self.main.content.mut_pointer().0,
+                modified: &mut self.main.modified,
+            };
+            while state.item != end {
+                f(index, &mut state);
+                index += 1;
+                if mem::size_of::<T>() == 0 {
+                    state.item = (state.item as *mut u8).wrapping_add(1) as *mut T;
+                } else {
+                    state.item = state.item.wrapping_add(1);
+                }
+            }
+        }
     }
 
-    fn nth(&mut self, n: usize) -> Option<Self::Item> {
-        let mut theref = unsafe { &mut *self.cowvec };
-        let len = (theref.end as usize - theref.item as usize) / (std::mem::size_of::<T>().max(1));
-        if n >= len {
-            None
+    /// Replace items using a closure which returns Some(new_value) to replace an item,
+    /// or None to keep it. The closure only gets shared access to each item, and
+    /// ownership is taken when it first returns Some.
+    pub fn map_in_place_lazy<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> Option<T>,
+    {
+        if let CowVecContent::Borrowed(borrowed) = self.main.content {
+            for (index, item) in borrowed.iter().enumerate() {
+                if let Some(replacement) = f(item) {
+                    let (owned, modified) = self.main.owned_parts();
+                    owned[index] = replacement;
+                    modified.insert(index);
+                    for (index, item) in owned.iter_mut().enumerate().skip(index + 1) {
+                        if let Some(replacement) = f(item) {
+                            *item = replacement;
+                            modified.insert(index);
+                        }
+                    }
+                    return;
+                }
+            }
         } else {
-            if mem::size_of::<T>() == 0 {
-                theref.item = (theref.item as *mut u8).wrapping_add(n) as *mut T;
-            } else {
-                theref.item = theref.item.wrapping_add(n);
+            let (owned, modified) = self.main.owned_parts();
+            for (index, item) in owned.iter_mut().enumerate() {
+                if let Some(replacement) = f(item) {
+                    *item = replacement;
+                    modified.insert(index);
+                }
             }
+        }
+    }
 
-            let retval = CowVecItemWrapper {
-                item: theref.item,
-                bad_wrapper_use_detector: self.bad_wrapper_use_detector,
-                owned: theref.is_owned(),
-                end: theref.end,
-                cowvec: self.cowvec,
-                phantom: PhantomData,
-            };
-            if mem::size_of::<T>() == 0 {
-                theref.item = (theref.item as *mut u8).wrapping_add(1) as *mut T;
-            } else {
-                theref.item = theref.item.wrapping_add(1);
+    /// Retain only the items for which the predicate returns true.
+    ///
+    /// If the predicate keeps all items, the CowVec keeps borrowing the initial Vec.
+    /// Otherwise ownership is taken, but only the retained items are cloned.
+    pub fn retain_lazy<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        if let CowVecContent::Borrowed(borrowed) = self.main.content {
+            for (index, item) in borrowed.iter().enumerate() {
+                if !f(item) {
+                    let mut retained = Vec::with_capacity(borrowed.len() - 1);
+                    retained.extend_from_slice(&borrowed[..index]);
+                    retained.extend(borrowed[index + 1..].iter().filter(|x| f(x)).cloned());
+                    self.main.content = CowVecContent::Owned(retained);
+                    self.main.modified.set_shifted();
+                    return;
+                }
             }
-            Some(retval)
+        } else {
+            // The modified indices are moved along with the retained items.
+            let (owned, modified) = self.main.owned_parts();
+            let mut retained_modified = ModifiedSet::new();
+            let (mut read, mut write) = (0, 0);
+            owned.retain(|item| {
+                let keep = f(item);
+                if keep {
+                    if modified.contains(read) {
+                        retained_modified.insert(write);
+                    }
+                    write += 1;
+                }
+                read += 1;
+                keep
+            });
+            if write != read || modified.shifted() {
+                retained_modified.set_shifted();
+            }
+            *modified = retained_modified;
         }
     }
 
-    /// Warning, this implementation of for_each is not as fast as it could be.
-    /// Please use CowVec::fast_for_each_mut instead for maximum performance.
-    #[inline]
-    fn for_each<F>(self, mut f: F)
+    /// Like retain_lazy, but the predicate may also modify the items. As with
+    /// fast_for_each_mut, you may have to use **item to access the item.
+    ///
+    /// While the contents are borrowed, a mutated item is cloned on its own. The Vec
+    /// is not cloned unless an item is removed or a retained item is mutated, and even then
+    /// only the retained items are cloned.
+    pub fn retain_mut_lazy<F>(&mut self, mut f: F)
     where
-        F: FnMut(Self::Item),
+        F: FnMut(&mut dyn FastForeachItem<Target = T>) -> bool,
     {
-        if *unsafe { &*self.bad_wrapper_use_detector } != WrapperState::Dead {
-            panic!("cow_vec_iterm: The placeholders returned by the mutable iterator of CowVec must not be retained. Only one wrapper can be alive at a time, but next() was called while the previous value had not been dropped.");
+        if let CowVecContent::Borrowed(borrowed) = self.main.content {
+            let mut retained: Option<Vec<T>> = None;
+            for (index, item) in borrowed.iter().enumerate() {
+                let mut state = BorrowedRetainItem { item, copy: None };
+                let keep = f(&mut state);
+                if retained.is_none() {
+                    if keep && state.copy.is_none() {
+                        continue;
+                    }
+                    let mut prefix = Vec::with_capacity(borrowed.len());
+                    prefix.extend_from_slice(&borrowed[..index]);
+                    retained = Some(prefix);
+                }
+                if keep {
+                    if let Some(retained) = retained.as_mut() {
+                        if state.copy.is_some() {
+                            self.main.modified.insert(retained.len());
+                        }
+                        retained.push(state.copy.unwrap_or_else(|| item.clone()));
+                    }
+                }
+            }
+            if let Some(retained) = retained {
+                if retained.len() != borrowed.len() {
+                    self.main.modified.set_shifted();
+                }
+                self.main.content = CowVecContent::Owned(retained);
+            }
+        } else {
+            // Writes are recorded in a scratch set, where each item is at index 0.
+            let (owned, modified) = self.main.owned_parts();
+            let mut retained_modified = ModifiedSet::new();
+            let mut written = ModifiedSet::new();
+            let (mut read, mut write) = (0, 0);
+            owned.retain_mut(|item| {
+                let item = item as *mut T;
+                let keep = f(&mut OwnedForEachItem {
+                    item,
+                    start: item,
+                    modified: &mut written,
+                });
+                if keep {
+                    if modified.contains(read) || !written.is_empty() {
+                        retained_modified.insert(write);
+                    }
+                    write += 1;
+                }
+                written.clear();
+                read += 1;
+                keep
+            });
+            if write != read || modified.shifted() {
+                retained_modified.set_shifted();
+            }
+            *modified = retained_modified;
         }
+    }
 
-        loop {
-            let retval;
-            {
-                let theref = unsafe { &mut *self.cowvec };
-                if theref.item == theref.end {
-                    break;
+    /// Like fast_for_each_mut, but visits the items in reverse order, starting
+    /// with the last one.
+    pub fn fast_for_each_mut_rev<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut dyn FastForeachItem<Target = T>),
+    {
+        let (ptr, len) = self.main.content.mut_pointer();
+        let end = if mem::size_of::<T>() == 0 {
+            (ptr as *mut u8).wrapping_add(self.len()) as *mut T
+        } else {
+            // Safety: Just pointer arithmetic. Slice address and length are immutable because of lifetimes.
+            unsafe { ptr.add(len) }
+        };
+
+        if !self.main.is_owned() {
+            let mut state = BorrowedFastForeachItem {
+                main: &mut self.main,
+                item: end,
+                end,
+            };
+
+            // The item pointer is derived from 'end' on each step, since both are
+            // moved to the owned clone if the closure takes ownership.
+            for offset_from_end in 1..=len {
+                if mem::size_of::<T>() == 0 {
+                    state.item = (state.end as *mut u8).wrapping_sub(offset_from_end) as *mut T;
+                } else {
+                    state.item = state.end.wrapping_sub(offset_from_end);
                 }
-                let self_item = theref.item;
+                f(&mut state);
+            }
+        } else {
+            let mut state = OwnedForEachItem {
+                item: end,
+                start: ptr,
+                modified: &mut self.main.modified,
+            };
+            while state.item != ptr {
                 if mem::size_of::<T>() == 0 {
-                    theref.item = (theref.item as *mut u8).wrapping_add(1) as *mut T;
+                    state.item = (state.item as *mut u8).wrapping_sub(1) as *mut T;
                 } else {
-                    theref.item = theref.item.wrapping_add(1);
+                    state.item = state.item.wrapping_sub(1);
                 }
-                retval = CowVecItemWrapper {
-                    item: self_item,
-                    bad_wrapper_use_detector: self.bad_wrapper_use_detector,
-                    owned: theref.is_owned(),
-                    end: theref.end,
-                    cowvec: self.cowvec,
-                    phantom: PhantomData,
-                };
+                f(&mut state);
             }
-            f(retval);
         }
     }
 
-    #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        // Safety: Cowvec must still be alive because of lifetime 'cowvec
-        let theref = unsafe { &mut *self.cowvec };
+    /// Iterate mutable over the CowVec, returning wrapped values which
+    /// implement DerefMut. If the returned wrapped value is accessed mutably, and not
+    /// only read, the CowVec will clone its contents and take ownership of the clone.
+    ///
+    /// If you don't need an iterator, but just need to traverse all values,
+    /// it is much faster to use the fast_for_each_mut() method instead.
+    pub fn iter_mut<'cowvec>(&'cowvec mut self) -> CowVecIter<'extvec, 'cowvec, T> {
+        self.iter_mut_range(..)
+    }
 
-        if *unsafe { &*self.bad_wrapper_use_detector } != WrapperState::Dead {
-            panic!("cow_vec_iterm: The placeholders returned by the mutable iterator of CowVec must not be retained. Only one wrapper can be alive at a time, but next() was called while the previous value had not been dropped.");
+    /// Like iter_mut, but only iterates over the items within the given range.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn iter_mut_range<'cowvec, R: RangeBounds<usize>>(
+        &'cowvec mut self,
+        range: R,
+    ) -> CowVecIter<'extvec, 'cowvec, T> {
+        if self.bad_wrapper_use_detector != WrapperState::Dead {
+            unreachable!("cow_vec_item: iter_mut was called while wrappers from a previous iter_mut were still alive! I had expected rust ownership rules to make this impossible. Please file a bug!");
         }
 
-        if theref.item == theref.end {
-            return None;
+        let (ptr, end) = self.main.content.mut_pointer_range(range);
+
+        self.main.item = ptr;
+        self.main.end = end;
+
+        CowVecIter {
+            cowvec: &mut self.main as *mut CowVecMain<T>,
+            bad_wrapper_use_detector: &mut self.bad_wrapper_use_detector as *mut WrapperState,
+            phantom: PhantomData,
         }
+    }
 
-        let self_item = theref.item;
-        *unsafe { &mut *self.bad_wrapper_use_detector } = WrapperState::Alive;
-        if mem::size_of::<T>() == 0 {
-            theref.item = (theref.item as *mut u8).wrapping_add(1) as *mut T;
-        } else {
-            theref.item = theref.item.wrapping_add(1);
+    /// Iterate mutably over the CowVec in chunks of chunk_size items, returning
+    /// wrapped slices. The last chunk is shorter if the length is not evenly
+    /// divisible by chunk_size. The CowVec takes ownership only if a chunk
+    /// is accessed mutably.
+    ///
+    /// Panics if chunk_size is 0.
+    pub fn chunks_mut<'cowvec>(
+        &'cowvec mut self,
+        chunk_size: usize,
+    ) -> CowVecChunksIter<'extvec, 'cowvec, T> {
+        if chunk_size == 0 {
+            panic!("cow_vec_item: chunk_size must be non-zero");
+        }
+        if self.bad_wrapper_use_detector != WrapperState::Dead {
+            unreachable!("cow_vec_item: chunks_mut was called while wrappers from a previous iterator were still alive! I had expected rust ownership rules to make this impossible. Please file a bug!");
         }
 
-        let retval = CowVecItemWrapper {
-            item: self_item,
-            bad_wrapper_use_detector: self.bad_wrapper_use_detector,
-            owned: theref.is_owned(),
-            end: theref.end,
-            cowvec: self.cowvec,
+        let (ptr, end) = self.main.content.mut_pointer_range(..);
+
+        self.main.item = ptr;
+        self.main.end = end;
+
+        CowVecChunksIter {
+            cowvec: &mut self.main as *mut CowVecMain<T>,
+            bad_wrapper_use_detector: &mut self.bad_wrapper_use_detector as *mut WrapperState,
+            chunk_size,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Iterate mutably over the CowVec, without any runtime checks for retained items.
+    ///
+    /// The returned iterator does not implement [Iterator](std::iter::Iterator), since
+    /// each item borrows the iterator itself. Use it with a `while let` loop:
+    ///
+    /// ```
+    /// # use cow_vec_item::CowVec;
+    /// let big_vec = vec![1, 2, 3];
+    /// let mut copy_on_write_ref = CowVec::from(&big_vec);
+    /// let mut iter = copy_on_write_ref.lending_iter_mut();
+    /// while let Some(mut item) = iter.next() {
+    ///     if *item == 2 {
+    ///         *item = 20; // The entire big_vec will be cloned here
+    ///     }
+    /// }
+    /// assert_eq!(*copy_on_write_ref, vec![1, 20, 3]);
+    /// ```
+    pub fn lending_iter_mut<'cowvec>(&'cowvec mut self) -> CowVecLendingIter<'extvec, 'cowvec, T> {
+        let (ptr, end) = self.main.content.mut_pointer_range(..);
+
+        self.main.item = ptr;
+        self.main.end = end;
+
+        CowVecLendingIter {
+            cowvec: &mut self.main as *mut CowVecMain<T>,
             phantom: PhantomData,
+        }
+    }
+
+    /// Iterate mutably over the CowVec, spreading the cost of the clone over the iteration.
+    ///
+    /// Like [lending_iter_mut](crate::CowVec::lending_iter_mut), this returns a lending
+    /// iterator. When an item at index i is first mutated, only the items up to and including
+    /// i are cloned. After that, each item is cloned as the iterator passes it. The remaining
+    /// items are cloned when the iterator is dropped, if the iteration stops early.
+    ///
+    /// Items can be overwritten using [set](crate::CowVecStreamingItem::set), which
+    /// avoids cloning the overwritten item at all.
+    ///
+    /// The CowVec takes ownership when the iterator is dropped. If the iterator is leaked
+    /// (using std::mem::forget), any modifications are lost.
+    ///
+    /// ```
+    /// # use cow_vec_item::CowVec;
+    /// let big_vec = vec![1, 2, 3, 4];
+    /// let mut copy_on_write_ref = CowVec::from(&big_vec);
+    /// let mut iter = copy_on_write_ref.streaming_iter_mut();
+    /// while let Some(mut item) = iter.next() {
+    ///     if *item >= 2 {
+    ///         let doubled = *item * 2;
+    ///         item.set(doubled); // Items 2, 3 and 4 are never cloned
+    ///     }
+    /// }
+    /// drop(iter);
+    /// assert_eq!(*copy_on_write_ref, [1, 4, 6, 8]);
+    /// ```
+    pub fn streaming_iter_mut<'cowvec>(
+        &'cowvec mut self,
+    ) -> CowVecStreamingIter<'extvec, 'cowvec, T> {
+        let source = match self.main.content {
+            CowVecContent::Borrowed(v) => Some(v),
+            CowVecContent::Owned(_) => None,
         };
+        CowVecStreamingIter {
+            cowvec: self,
+            source,
+            prefix: None,
+            index: 0,
+        }
+    }
+
+    /// Iterate mutably over the CowVec, returning mutable references.
+    /// This method immediately, eagerly, takes ownership of the wrapped
+    /// Vec (cloning if necessary).
+    /// In most cases what you want is the iter_mut method, which can avoid taking
+    /// ownership unless necessary. This method can be useful though, since the
+    /// reduced book-keeping makes it run significantly faster.
+    /// Since the writes can't be tracked, all items are marked as modified.
+    pub fn eager_cloned_iter_mut<'cowvec>(&'cowvec mut self) -> impl Iterator<Item = &'cowvec mut T>
+    where
+        'extvec: 'cowvec,
+    {
+        let (v, modified) = self.main.owned_parts();
+        modified.insert_all();
+        v.iter_mut()
+    }
+
+    /// Returns true if the item at the given index has been written since the CowVec
+    /// was created (or since clear_modified was called).
+    ///
+    /// An item counts as written if it has been accessed mutably, even if its value
+    /// did not change. Items added by push, insert or extend count as written. Methods
+    /// which give mutable access to all the contents at once, such as to_mut, DerefMut
+    /// and eager_cloned_iter_mut, mark all items as written.
+    pub fn is_modified(&self, index: usize) -> bool {
+        index < self.len() && self.main.modified.contains(index)
+    }
+    /// Iterate over the indices of the written items, in order. See
+    /// [is_modified](crate::CowVec::is_modified).
+    pub fn modified_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.main.modified.indices(self.len())
+    }
+    /// Iterate over the written items as ranges of consecutive indices, in order.
+    /// See [is_modified](crate::CowVec::is_modified).
+    pub fn modified_ranges(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.main.modified.ranges(self.len())
+    }
+    /// Forget which items have been written, for example after processing them.
+    /// This does not affect the contents.
+    pub fn clear_modified(&mut self) {
+        self.main.modified.clear();
+    }
+
+    /// Returns the Vec (or slice) the CowVec was created from. This is still available
+    /// after ownership has been taken. For a CowVec created using from_owned, this is
+    /// an empty slice.
+    pub fn original(&self) -> &'extvec [T] {
+        self.origin.unwrap_or(&[])
+    }
+
+    /// Set the item at the given index back to its original value, and no longer report
+    /// it as modified. See [revert_range](crate::CowVec::revert_range).
+    pub fn revert(&mut self, index: usize) {
+        self.revert_range(index..=index);
+    }
+
+    /// Set the items in the given range back to their original values, and no longer
+    /// report them as modified (unless all items have been marked as modified, see
+    /// [is_modified](crate::CowVec::is_modified)). If the contents are still borrowed,
+    /// nothing needs to be done.
+    ///
+    /// Panics if the range is out of bounds for either the current or the original
+    /// items, or if items have been inserted or removed before the end, so that the
+    /// positions of the items no longer match the original.
+    pub fn revert_range<R: RangeBounds<usize>>(&mut self, range: R) {
+        let (start, end) = resolve_range(range, self.len());
+        let original = self.original();
+        if end > original.len() {
+            panic!(
+                "cow_vec_item: revert range end {} out of range for original of length {}",
+                end,
+                original.len()
+            );
+        }
+        if self.main.modified.shifted() {
+            panic!("cow_vec_item: revert called after items were inserted or removed before the end");
+        }
+        if let CowVecContent::Owned(v) = &mut self.main.content {
+            v[start..end].clone_from_slice(&original[start..end]);
+            for index in start..end {
+                self.main.modified.remove(index);
+            }
+        }
+    }
+
+    /// Go back to borrowing the original Vec, dropping the owned clone and all changes.
+    ///
+    /// A CowVec created using from_owned has no original Vec to go back to, so this
+    /// does nothing.
+    pub fn reset(&mut self) {
+        if let Some(origin) = self.origin {
+            self.main.content = CowVecContent::Borrowed(origin);
+            self.main.modified = ModifiedSet::new();
+        }
+    }
+}
+
+impl<'extvec, T: Clone + PartialEq> CowVec<'extvec, T> {
+    /// Returns a [Patch](crate::Patch) describing the changes from the borrowed
+    /// Vec to the current contents. Applying the patch to a copy of the borrowed Vec
+    /// gives the current contents. For a CowVec created using from_owned, the patch
+    /// inserts all items.
+    ///
+    /// While the contents are still borrowed, nothing needs to be compared. Otherwise
+    /// the items are compared with the borrowed ones, skipping the common prefix
+    /// and suffix.
+    pub fn diff(&self) -> Patch<T> {
+        if let CowVecContent::Borrowed(borrowed) = self.main.content {
+            if mem::size_of::<T>() != 0 {
+                // The borrowed slice can only have been narrowed
+                let origin = self.original();
+                let start = item_index(origin.as_ptr(), borrowed.as_ptr());
+                return Patch::narrowing(origin.len(), start, start + borrowed.len());
+            }
+        }
+        patch::diff(self.original(), self)
+    }
+
+    /// If the contents are owned but equal to the borrowed Vec, drop the owned clone
+    /// and go back to borrowing. This is useful when a write has later been undone.
+    /// Returns true if the clone was dropped.
+    pub fn try_reborrow(&mut self) -> bool {
+        if let CowVecContent::Owned(v) = &self.main.content {
+            if self.origin.is_some_and(|origin| v[..] == *origin) {
+                self.reset();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Enable or disable comparing the owned contents with the borrowed Vec in
+    /// [into_changed](crate::CowVec::into_changed), so that it returns None if an
+    /// owned clone turns out to be equal. This is disabled by default, since the
+    /// comparison visits every item. The CowVec returned by split_off starts with
+    /// this disabled.
+    ///
+    /// [into_owned](crate::CowVec::into_owned) does not use this check: it has to
+    /// return a Vec either way, and an owned clone equal to the borrowed Vec is
+    /// already the cheapest one to return.
+    pub fn set_auto_reborrow(&mut self, enabled: bool) {
+        self.auto_reborrow = enabled;
+    }
+
+    /// Returns the contents as a Vec, or None if nothing has changed compared to the
+    /// borrowed Vec.
+    ///
+    /// While the contents are still borrowed, they have changed only if items have been
+    /// removed, and the remaining items are then cloned into the returned Vec. Once
+    /// ownership has been taken, the owned Vec is returned without cloning. It is
+    /// considered changed, unless [set_auto_reborrow](crate::CowVec::set_auto_reborrow)
+    /// has been enabled and it compares equal to the borrowed Vec.
+    pub fn into_changed(self) -> Option<Vec<T>> {
+        let origin = self.origin;
+        match self.main.content {
+            CowVecContent::Borrowed(v) => {
+                if v.len() == self.original().len() {
+                    None
+                } else {
+                    Some(v.to_vec())
+                }
+            }
+            CowVecContent::Owned(v) => {
+                if self.auto_reborrow && origin.is_some_and(|origin| v[..] == *origin) {
+                    None
+                } else {
+                    Some(v)
+                }
+            }
+        }
+    }
+
+    /// Set the item at the given index, but only if it differs from the
+    /// current value. Ownership is only taken if the value actually changes.
+    /// Returns true if the value was changed.
+    ///
+    /// Panics if index is out of bounds.
+    pub fn set_if_changed(&mut self, index: usize, value: T) -> bool {
+        if self[index] == value {
+            return false;
+        }
+        self[index] = value;
+        true
+    }
+
+    /// Iterate mutably over the CowVec, giving the closure a plain &mut T.
+    ///
+    /// As long as the contents are borrowed, the closure is run on a temporary
+    /// clone of each item, and ownership is only taken if the result differs from
+    /// the original. This means helper functions which take &mut T and write back
+    /// the same value don't cause the Vec to be cloned. After ownership has been taken,
+    /// the closure is run directly on the owned items.
+    ///
+    /// Only the items which end up differing from the borrowed ones are marked as
+    /// modified. If the contents were already owned and items have been inserted or
+    /// removed before the end, the items can't be compared, and all are marked.
+    pub fn speculative_for_each_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut T),
+    {
+        if let CowVecContent::Borrowed(borrowed) = self.main.content {
+            for (index, item) in borrowed.iter().enumerate() {
+                let mut temp = item.clone();
+                f(&mut temp);
+                if temp != *item {
+                    let (owned, modified) = self.main.owned_parts();
+                    owned[index] = temp;
+                    modified.insert(index);
+                    for (index, item) in owned.iter_mut().enumerate().skip(index + 1) {
+                        f(item);
+                        if *item != borrowed[index] {
+                            modified.insert(index);
+                        }
+                    }
+                    return;
+                }
+            }
+        } else {
+            let origin = if self.main.modified.shifted() { None } else { self.origin };
+            let (owned, modified) = self.main.owned_parts();
+            for (index, item) in owned.iter_mut().enumerate() {
+                f(item);
+                if !modified.contains(index)
+                    && origin.and_then(|origin| origin.get(index)).is_none_or(|original| item != original)
+                {
+                    modified.insert(index);
+                }
+            }
+        }
+    }
+
+    /// Apply a number of (index, value) updates, using set_if_changed for each of them.
+    /// Ownership is only taken when the first update which actually changes a value is
+    /// found. Returns the number of updates which changed a value.
+    ///
+    /// Panics if any index is out of bounds.
+    pub fn apply_updates<I>(&mut self, updates: I) -> usize
+    where
+        I: IntoIterator<Item = (usize, T)>,
+    {
+        let mut changed = 0;
+        for (index, value) in updates {
+            if self.set_if_changed(index, value) {
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+/// Iterator over the items removed by [CowVec::drain](crate::CowVec::drain).
+pub struct CowVecDrain<'a, T> {
+    inner: CowVecDrainInner<'a, T>,
+}
+
+enum CowVecDrainInner<'a, T> {
+    Owned(std::vec::Drain<'a, T>),
+    Borrowed(std::slice::Iter<'a, T>),
+}
+
+impl<'a, T: Clone> Iterator for CowVecDrain<'a, T> {
+    type Item = T;
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        match &mut self.inner {
+            CowVecDrainInner::Owned(drain) => drain.next(),
+            CowVecDrainInner::Borrowed(iter) => iter.next().cloned(),
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.inner {
+            CowVecDrainInner::Owned(drain) => drain.size_hint(),
+            CowVecDrainInner::Borrowed(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<'a, T: Clone> DoubleEndedIterator for CowVecDrain<'a, T> {
+    #[inline]
+    fn next_back(&mut self) -> Option<T> {
+        match &mut self.inner {
+            CowVecDrainInner::Owned(drain) => drain.next_back(),
+            CowVecDrainInner::Borrowed(iter) => iter.next_back().cloned(),
+        }
+    }
+}
+
+impl<'a, T: Clone> ExactSizeIterator for CowVecDrain<'a, T> {}
+
+/// Mutable smart iterator over a CowVec. This is an internal
+/// detail that shouldn't be used directly.
+pub struct CowVecIter<'extvec, 'cowvec, T> {
+    // The lifetime 'cowvec is the lifetime of CowVec object itself
+    cowvec: *mut CowVecMain<'extvec, T>,
+    bad_wrapper_use_detector: *mut WrapperState,
+    phantom: PhantomData<&'cowvec mut ()>,
+}
+
+impl<'extvec, 'cowvec, T: Clone> Iterator for CowVecIter<'extvec, 'cowvec, T>
+where
+    'extvec: 'cowvec,
+{
+    type Item = CowVecItemWrapper<'extvec, 'cowvec, T>;
+
+    fn count(self) -> usize {
+        let theref = unsafe { &*self.cowvec };
+        (theref.end as usize - theref.item as usize) / (std::mem::size_of::<T>().max(1))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let theref = unsafe { &*self.cowvec };
+        let size = (theref.end as usize - theref.item as usize) / (std::mem::size_of::<T>().max(1));
+        (size, Some(size))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let theref = unsafe { &mut *self.cowvec };
+        let len = (theref.end as usize - theref.item as usize) / (std::mem::size_of::<T>().max(1));
+        if n >= len {
+            None
+        } else {
+            if mem::size_of::<T>() == 0 {
+                theref.item = (theref.item as *mut u8).wrapping_add(n) as *mut T;
+            } else {
+                theref.item = theref.item.wrapping_add(n);
+            }
+
+            let retval = CowVecItemWrapper {
+                item: theref.item,
+                bad_wrapper_use_detector: self.bad_wrapper_use_detector,
+                owned: theref.is_owned(),
+                cowvec: self.cowvec,
+                phantom: PhantomData,
+            };
+            if mem::size_of::<T>() == 0 {
+                theref.item = (theref.item as *mut u8).wrapping_add(1) as *mut T;
+            } else {
+                theref.item = theref.item.wrapping_add(1);
+            }
+            Some(retval)
+        }
+    }
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        // Safety: Cowvec must still be alive because of lifetime 'cowvec
+        let theref = unsafe { &mut *self.cowvec };
+
+        if *unsafe { &*self.bad_wrapper_use_detector } != WrapperState::Dead {
+            panic!("cow_vec_iterm: The placeholders returned by the mutable iterator of CowVec must not be retained. Only one wrapper can be alive at a time, but next() was called while the previous value had not been dropped.");
+        }
+
+        if theref.item == theref.end {
+            return None;
+        }
+
+        let self_item = theref.item;
+        *unsafe { &mut *self.bad_wrapper_use_detector } = WrapperState::Alive;
+        if mem::size_of::<T>() == 0 {
+            theref.item = (theref.item as *mut u8).wrapping_add(1) as *mut T;
+        } else {
+            theref.item = theref.item.wrapping_add(1);
+        }
+
+        let retval = CowVecItemWrapper {
+            item: self_item,
+            bad_wrapper_use_detector: self.bad_wrapper_use_detector,
+            owned: theref.is_owned(),
+            cowvec: self.cowvec,
+            phantom: PhantomData,
+        };
+
+        Some(retval)
+    }
+}
+
+
+impl<'extvec, 'cowvec, T: Clone> DoubleEndedIterator for CowVecIter<'extvec, 'cowvec, T>
+where
+    'extvec: 'cowvec,
+{
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        // Safety: Cowvec must still be alive because of lifetime 'cowvec
+        let theref = unsafe { &mut *self.cowvec };
+
+        if *unsafe { &*self.bad_wrapper_use_detector } != WrapperState::Dead {
+            panic!("cow_vec_iterm: The placeholders returned by the mutable iterator of CowVec must not be retained. Only one wrapper can be alive at a time, but next_back() was called while the previous value had not been dropped.");
+        }
+
+        let len = (theref.end as usize - theref.item as usize) / (std::mem::size_of::<T>().max(1));
+        if n >= len {
+            theref.end = theref.item;
+            return None;
+        }
+
+        if mem::size_of::<T>() == 0 {
+            theref.end = (theref.end as *mut u8).wrapping_sub(n + 1) as *mut T;
+        } else {
+            theref.end = theref.end.wrapping_sub(n + 1);
+        }
+        *unsafe { &mut *self.bad_wrapper_use_detector } = WrapperState::Alive;
+
+        Some(CowVecItemWrapper {
+            item: theref.end,
+            bad_wrapper_use_detector: self.bad_wrapper_use_detector,
+            owned: theref.is_owned(),
+            cowvec: self.cowvec,
+            phantom: PhantomData,
+        })
+    }
+
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.nth_back(0)
+    }
+}
+
+impl<'extvec, 'cowvec, T: Clone> ExactSizeIterator for CowVecIter<'extvec, 'cowvec, T> where
+    'extvec: 'cowvec
+{
+}
+
+impl<'extvec, 'cowvec, T: Clone> FusedIterator for CowVecIter<'extvec, 'cowvec, T> where
+    'extvec: 'cowvec
+{
+}
+
+
+/// Mutable lending iterator over a CowVec, which clones the borrowed items as it
+/// passes them. Returned by [CowVec::streaming_iter_mut](crate::CowVec::streaming_iter_mut).
+pub struct CowVecStreamingIter<'extvec, 'cowvec, T: Clone> {
+    cowvec: &'cowvec mut CowVec<'extvec, T>,
+    // The borrowed items, if the CowVec did not own its contents when the iteration started
+    source: Option<&'extvec [T]>,
+    // The items cloned so far, once an item has been mutated
+    prefix: Option<Vec<T>>,
+    index: usize,
+}
+
+/// A value being iterated over by [CowVecStreamingIter](crate::CowVecStreamingIter).
+pub struct CowVecStreamingItem<'extvec, 'cowvec, 'iter, T: Clone> {
+    iter: &'iter mut CowVecStreamingIter<'extvec, 'cowvec, T>,
+    index: usize,
+}
+
+impl<'extvec, 'cowvec, T: Clone> CowVecStreamingIter<'extvec, 'cowvec, T> {
+    /// Advance the iterator, returning the next item. The item must be dropped
+    /// before next can be called again.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<CowVecStreamingItem<'extvec, 'cowvec, '_, T>> {
+        if self.index == self.cowvec.len() {
+            self.finish();
+            return None;
+        }
+        if let (Some(source), Some(prefix)) = (self.source, self.prefix.as_mut()) {
+            // The items passed without being mutated are cloned here
+            while prefix.len() < self.index {
+                prefix.push(source[prefix.len()].clone());
+            }
+        }
+        let index = self.index;
+        self.index += 1;
+        Some(CowVecStreamingItem { iter: self, index })
+    }
+
+    /// Take ownership of the cloned prefix, cloning any items not yet passed.
+    fn finish(&mut self) {
+        if let (Some(source), Some(mut prefix)) = (self.source, self.prefix.take()) {
+            prefix.extend_from_slice(&source[prefix.len()..]);
+            self.cowvec.main.content = CowVecContent::Owned(prefix);
+            self.source = None;
+        }
+    }
+
+    /// Returns the cloned prefix, creating it from the items before index if needed.
+    fn prefix_up_to(&mut self, source: &'extvec [T], index: usize) -> &mut Vec<T> {
+        self.prefix.get_or_insert_with(|| {
+            let mut prefix = Vec::with_capacity(source.len());
+            prefix.extend_from_slice(&source[..index]);
+            prefix
+        })
+    }
+}
+
+impl<'extvec, 'cowvec, T: Clone> Drop for CowVecStreamingIter<'extvec, 'cowvec, T> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl<'extvec, 'cowvec, 'iter, T: Clone> CowVecStreamingItem<'extvec, 'cowvec, 'iter, T> {
+    /// Overwrite the item. Unlike assigning through DerefMut, this does not
+    /// clone the overwritten item.
+    pub fn set(&mut self, value: T) {
+        let index = self.index;
+        match self.iter.source {
+            None => self.iter.cowvec[index] = value,
+            Some(source) => {
+                self.iter.cowvec.main.modified.insert(index);
+                let prefix = self.iter.prefix_up_to(source, index);
+                if prefix.len() == index {
+                    prefix.push(value);
+                } else {
+                    prefix[index] = value;
+                }
+            }
+        }
+    }
+}
+
+impl<'extvec, 'cowvec, 'iter, T: Clone> Deref for CowVecStreamingItem<'extvec, 'cowvec, 'iter, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        match (self.iter.source, &self.iter.prefix) {
+            (None, _) => &self.iter.cowvec[self.index],
+            (Some(_), Some(prefix)) if self.index < prefix.len() => &prefix[self.index],
+            (Some(source), _) => &source[self.index],
+        }
+    }
+}
+
+impl<'extvec, 'cowvec, 'iter, T: Clone> DerefMut
+    for CowVecStreamingItem<'extvec, 'cowvec, 'iter, T>
+{
+    fn deref_mut(&mut self) -> &mut T {
+        let index = self.index;
+        match self.iter.source {
+            None => &mut self.iter.cowvec[index],
+            Some(source) => {
+                self.iter.cowvec.main.modified.insert(index);
+                let prefix = self.iter.prefix_up_to(source, index);
+                if prefix.len() == index {
+                    prefix.push(source[index].clone());
+                }
+                &mut prefix[index]
+            }
+        }
+    }
+}
+
+/// Mutable lending iterator over a CowVec, returned by
+/// [CowVec::lending_iter_mut](crate::CowVec::lending_iter_mut).
+///
+/// Retaining an item while requesting the next one is rejected at compile time:
+///
+/// ```compile_fail
+/// # use cow_vec_item::CowVec;
+/// let big_vec = vec![1, 2];
+/// let mut copy_on_write_ref = CowVec::from(&big_vec);
+/// let mut iter = copy_on_write_ref.lending_iter_mut();
+/// let first = iter.next().unwrap();
+/// let second = iter.next().unwrap();
+/// assert_eq!(*first + *second, 3);
+/// ```
+pub struct CowVecLendingIter<'extvec, 'cowvec, T> {
+    // The lifetime 'cowvec is the lifetime of CowVec object itself
+    cowvec: *mut CowVecMain<'extvec, T>,
+    phantom: PhantomData<&'cowvec mut ()>,
+}
+
+impl<'extvec, 'cowvec, T: Clone> CowVecLendingIter<'extvec, 'cowvec, T>
+where
+    'extvec: 'cowvec,
+{
+    /// Returns the number of items not yet iterated over.
+    pub fn len(&self) -> usize {
+        let theref = unsafe { &*self.cowvec };
+        (theref.end as usize - theref.item as usize) / (std::mem::size_of::<T>().max(1))
+    }
+
+    /// Returns true if there are no more items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Advance the iterator, returning the next item. The item must be dropped
+    /// before next (or next_back) can be called again.
+    #[inline]
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<CowVecLendingItem<'extvec, '_, T>> {
+        // Safety: Cowvec must still be alive because of lifetime 'cowvec
+        let theref = unsafe { &mut *self.cowvec };
+        if theref.item == theref.end {
+            return None;
+        }
+        let self_item = theref.item;
+        if mem::size_of::<T>() == 0 {
+            theref.item = (theref.item as *mut u8).wrapping_add(1) as *mut T;
+        } else {
+            theref.item = theref.item.wrapping_add(1);
+        }
+        Some(CowVecLendingItem {
+            item: self_item,
+            cowvec: self.cowvec,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Advance the iterator from the back, returning the last remaining item.
+    #[inline]
+    pub fn next_back(&mut self) -> Option<CowVecLendingItem<'extvec, '_, T>> {
+        // Safety: Cowvec must still be alive because of lifetime 'cowvec
+        let theref = unsafe { &mut *self.cowvec };
+        if theref.item == theref.end {
+            return None;
+        }
+        if mem::size_of::<T>() == 0 {
+            theref.end = (theref.end as *mut u8).wrapping_sub(1) as *mut T;
+        } else {
+            theref.end = theref.end.wrapping_sub(1);
+        }
+        Some(CowVecLendingItem {
+            item: theref.end,
+            cowvec: self.cowvec,
+            phantom: PhantomData,
+        })
+    }
+}
+
+
+/// Mutable iterator over chunks of a CowVec, returned by
+/// [CowVec::chunks_mut](crate::CowVec::chunks_mut).
+pub struct CowVecChunksIter<'extvec, 'cowvec, T> {
+    // The lifetime 'cowvec is the lifetime of CowVec object itself
+    cowvec: *mut CowVecMain<'extvec, T>,
+    bad_wrapper_use_detector: *mut WrapperState,
+    chunk_size: usize,
+    phantom: PhantomData<&'cowvec mut ()>,
+}
+
+impl<'extvec, 'cowvec, T: Clone> Iterator for CowVecChunksIter<'extvec, 'cowvec, T>
+where
+    'extvec: 'cowvec,
+{
+    type Item = CowVecChunkWrapper<'extvec, 'cowvec, T>;
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let theref = unsafe { &*self.cowvec };
+        let len = (theref.end as usize - theref.item as usize) / (std::mem::size_of::<T>().max(1));
+        let chunks = len.div_ceil(self.chunk_size);
+        (chunks, Some(chunks))
+    }
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        // Safety: Cowvec must still be alive because of lifetime 'cowvec
+        let theref = unsafe { &mut *self.cowvec };
+
+        if *unsafe { &*self.bad_wrapper_use_detector } != WrapperState::Dead {
+            panic!("cow_vec_iterm: The placeholders returned by the mutable chunk iterator of CowVec must not be retained. Only one wrapper can be alive at a time, but next() was called while the previous value had not been dropped.");
+        }
+
+        if theref.item == theref.end {
+            return None;
+        }
+
+        let remaining =
+            (theref.end as usize - theref.item as usize) / (std::mem::size_of::<T>().max(1));
+        let len = remaining.min(self.chunk_size);
+        let self_item = theref.item;
+        *unsafe { &mut *self.bad_wrapper_use_detector } = WrapperState::Alive;
+        if mem::size_of::<T>() == 0 {
+            theref.item = (theref.item as *mut u8).wrapping_add(len) as *mut T;
+        } else {
+            theref.item = theref.item.wrapping_add(len);
+        }
+
+        Some(CowVecChunkWrapper {
+            item: self_item,
+            len,
+            bad_wrapper_use_detector: self.bad_wrapper_use_detector,
+            owned: theref.is_owned(),
+            marked: false,
+            cowvec: self.cowvec,
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl<'extvec, 'cowvec, T: Clone> ExactSizeIterator for CowVecChunksIter<'extvec, 'cowvec, T> where
+    'extvec: 'cowvec
+{
+}
+
+impl<'extvec, 'cowvec, T: Clone> FusedIterator for CowVecChunksIter<'extvec, 'cowvec, T> where
+    'extvec: 'cowvec
+{
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::CowVec;
+    use crate::{CowVecItemWrapper, PatchOp};
+    use std::cell::Cell;
+    use std::ops::{ControlFlow, Deref, DerefMut};
+
+    thread_local!(static CLONES: Cell<usize> = const { Cell::new(0) });
+
+    /// An item which counts how many times items are cloned on the current thread.
+    struct Counted(i32);
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            CLONES.with(|c| c.set(c.get() + 1));
+            Counted(self.0)
+        }
+    }
+    impl Counted {
+        /// Returns the number of clones since the last call, and starts counting from 0.
+        fn take_clones() -> usize {
+            CLONES.with(|c| c.replace(0))
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_ensure_retaining_iterated_value_causes_panic() {
+        let v = vec![32i32, 33i32];
+        let mut temp = CowVec::from(&v);
+
+        {
+            let mut iter = temp.iter_mut();
+
+            let mut a1 = iter.next().unwrap();
+            let mut a2 = iter.next().unwrap();
+
+            let a1_mut = a1.deref_mut();
+            let a2_mut = a2.deref_mut();
+            *a1_mut += 1;
+            *a2_mut += 1;
+        }
+    }
+    #[test]
+    fn test_two_back_to_back_iter_mut_should_be_allowed() {
+        let v = vec![32i32, 33i32];
+        let mut temp = CowVec::from(&v);
+
+        {
+            let mut _iter = temp.iter_mut();
+            let mut _iter = temp.iter_mut();
+        }
+    }
+    #[test]
+    fn test_simultaneous_iter_allowed() {
+        let v = vec![32i32, 33i32];
+        let temp = CowVec::from(&v);
+
+        {
+            let mut iter_a = temp.iter();
+            let mut iter_b = temp.iter();
+            assert_eq!(*iter_a.next().unwrap(), *iter_b.next().unwrap());
+        }
+    }
+    #[test]
+    fn test_cornercase1() {
+        let v = vec![32i32, 33i32];
+        let mut temp = CowVec::from(&v);
+
+        {
+            {
+                let mut wrapper;
+                let mut iter_a = temp.iter_mut();
+                wrapper = iter_a.next().unwrap();
+                *wrapper = 73;
+            }
+            let mut iter_b = temp.iter_mut();
+            let _first = iter_b.next().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_cornercase2() {
+        let v = vec![32i32, 33i32];
+        let mut temp = CowVec::from(&v);
+
+        {
+            let mut iter_a;
+            {
+                let mut wrapper;
+                iter_a = temp.iter_mut();
+                wrapper = iter_a.next().unwrap();
+                *wrapper = 73;
+            }
+            let _x = iter_a;
+        }
+    }
+
+    #[test]
+    fn test_cornercase3() {
+        let mut v = Vec::new();
+        {
+            let mut temp;
+            v.push(32i32);
+            v.push(33i32);
+            temp = CowVec::from(&v);
+            let _val = temp.iter_mut().next().unwrap();
+        }
+        v.push(32);
+    }
+
+    #[test]
+    fn test_ref_items() {
+        let mut v2 = Vec::new();
+        let mut temp;
+        {
+            let v = [32i32, 33i32];
+            v2.push(&v[0]);
+            v2.push(&v[1]);
+
+            temp = CowVec::from(&v2);
+            temp.push(&37);
+        }
+    }
+
+    #[test]
+    fn test_with_empty_vec() {
+        let primary: Vec<i32> = vec![];
+        let mut cowvec = CowVec::from(&primary);
+
+        cowvec.ensure_owned();
+        assert_eq!(cowvec.len(), 0);
+        let _ = cowvec.to_owned();
+    }
+
+    #[test]
+    fn test_eager_cloned_iter_mut() {
+        let mut temp = CowVec::from_owned(vec![1, 2, 3]);
+        let output: Vec<_> = temp.eager_cloned_iter_mut().collect();
+        assert_eq!(*output[0], 1);
+        assert_eq!(*output[1], 2);
+        assert_eq!(*output[2], 3);
+    }
+    #[test]
+    fn test_basics1() {
+        let v = vec![32i32, 33i32];
+        let mut temp = CowVec::from(&v);
+
+        {
+            let temp2 = &mut temp;
+            {
+                let mut iter = temp2.iter_mut();
+
+                assert_eq!(*iter.next().unwrap(), 32);
+                assert_eq!(*iter.next().unwrap(), 33);
+            }
+        }
+        {
+            let mut iter = temp.iter_mut();
+            assert_eq!(*iter.next().unwrap(), 32);
+        }
+        assert!(!temp.is_owned());
+    }
+
+    #[test]
+    #[allow(clippy::iter_nth_zero)] // nth(0) is what is being tested
+    fn test_iter_nth() {
+        let v = [32i32, 33i32];
+
+        let mut i = v.iter();
+        i.next().unwrap();
+
+        assert_eq!(*i.nth(0).unwrap(), 33);
+        assert_eq!(i.nth(1), None);
+    }
+
+    #[test]
+    #[allow(clippy::iter_nth_zero)] // nth(0) is what is being tested
+    fn test_iter_nth2() {
+        let v = vec![32i32, 33i32, 34i32, 35i32];
+
+        let mut cowvec = CowVec::from(&v);
+
+        let mut i = cowvec.iter_mut();
+        let v0 = i.nth(0).unwrap();
+        assert_eq!(*v0, 32);
+        let v2 = i.nth(1).unwrap();
+        assert_eq!(*v2, 34);
+        let v3 = i.nth(0).unwrap();
+        assert_eq!(*v3, 35);
+        let v4 = i.nth(1);
+        assert!(v4.is_none());
+    }
+
+    #[test]
+    fn test_iter_count_and_size_hint() {
+        let v = vec![32i32, 33i32];
+        let mut temp = CowVec::from(&v);
+        assert_eq!(temp.iter_mut().count(), 2);
+        let mut it = temp.iter_mut();
+        it.next().unwrap();
+        assert_eq!(it.size_hint().0, 1);
+        assert_eq!(it.size_hint().1, Some(1));
+        assert_eq!(it.count(), 1);
+        let mut it = temp.iter_mut();
+        it.next().unwrap();
+        it.next().unwrap();
+        assert_eq!(it.count(), 0);
+    }
+    #[test]
+    fn test_iter_mut_rev() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut temp = CowVec::from(&v);
+        let seen: Vec<i32> = temp.iter_mut().rev().map(|item| *item).collect();
+        assert_eq!(seen, vec![4, 3, 2, 1]);
+        assert!(!temp.is_owned());
+
+        for mut item in temp.iter_mut().rev() {
+            if *item == 3 {
+                *item = 30;
+            }
+        }
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![1, 2, 30, 4]);
+        assert_eq!(v, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_iter_mut_double_ended() {
+        let v = vec![1i32, 2, 3, 4, 5];
+        let mut temp = CowVec::from(&v);
+        {
+            let mut iter = temp.iter_mut();
+            assert_eq!(*iter.next().unwrap(), 1);
+            {
+                let mut last = iter.next_back().unwrap();
+                *last = 50;
+            }
+            assert_eq!(iter.size_hint(), (3, Some(3)));
+            {
+                let mut second = iter.next().unwrap();
+                *second = 20;
+            }
+            {
+                let mut fourth = iter.next_back().unwrap();
+                *fourth = 40;
+            }
+            assert_eq!(*iter.next().unwrap(), 3);
+            assert!(iter.next_back().is_none());
+            assert!(iter.next().is_none());
+        }
+        assert_eq!(*temp, vec![1, 20, 3, 40, 50]);
+        assert_eq!(v, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_iter_mut_nth_back() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut temp = CowVec::from(&v);
+        let mut iter = temp.iter_mut();
+        assert_eq!(*iter.nth_back(1).unwrap(), 3);
+        assert_eq!(*iter.next().unwrap(), 1);
+        assert!(iter.nth_back(2).is_none());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_zero_size_iter_mut_rev() {
+        let v = vec![(), (), ()];
+        let mut temp = CowVec::from(&v);
+        let mut count = 0;
+        for mut item in temp.iter_mut().rev() {
+            *item = ();
+            count += 1;
+        }
+        assert_eq!(count, 3);
+        assert_eq!(temp.len(), 3);
+    }
+
+    #[test]
+    fn test_fast_for_each_rev() {
+        let v = vec![32i32, 33, 34];
+        let mut temp = CowVec::from(&v);
+        let mut seen = Vec::new();
+        temp.fast_for_each_mut_rev(|item| seen.push(**item));
+        assert_eq!(seen, vec![34, 33, 32]);
+        assert!(!temp.is_owned());
+
+        let mut seen = Vec::new();
+        temp.fast_for_each_mut_rev(|item| {
+            seen.push(**item);
+            if **item == 33 {
+                **item = 47;
+            }
+        });
+        assert_eq!(seen, vec![34, 33, 32]);
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![32, 47, 34]);
+        assert_eq!(v, vec![32, 33, 34]);
+
+        let mut seen = Vec::new();
+        temp.fast_for_each_mut_rev(|item| seen.push(**item));
+        assert_eq!(seen, vec![34, 47, 32]);
+    }
+
+    #[test]
+    fn test_zero_size_fast_for_each_rev() {
+        let v = vec![(), ()];
+        let mut temp = CowVec::from(&v);
+        let mut count = 0;
+        temp.fast_for_each_mut_rev(|item| {
+            **item = ();
+            count += 1;
+        });
+        assert_eq!(count, 2);
+        assert!(temp.is_owned());
+    }
+
+    #[test]
+    fn test_iter_mut_exact_size() {
+        let v = vec![1i32, 2, 3];
+        let mut temp = CowVec::from(&v);
+        let mut iter = temp.iter_mut();
+        assert_eq!(iter.len(), 3);
+        iter.next().unwrap();
+        assert_eq!(iter.len(), 2);
+        iter.next_back().unwrap();
+        assert_eq!(iter.len(), 1);
+        iter.next().unwrap();
+        assert_eq!(iter.len(), 0);
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_iter_mut_fold() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut temp = CowVec::from(&v);
+        let sum: i32 = temp.iter_mut().map(|item| *item).sum();
+        assert_eq!(sum, 10);
+        assert!(!temp.is_owned());
+
+        let changed = temp.iter_mut().rev().fold(0, |acc, mut item| {
+            if *item % 2 == 0 {
+                *item *= 10;
+                acc + 1
+            } else {
+                acc
+            }
+        });
+        assert_eq!(changed, 2);
+        assert_eq!(*temp, vec![1, 20, 3, 40]);
+        assert_eq!(v, vec![1, 2, 3, 4]);
+
+        let collected: Vec<i32> = temp.iter_mut().map(|item| *item).collect();
+        assert_eq!(collected, vec![1, 20, 3, 40]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_iter_mut_partition_retains_wrappers() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut temp = CowVec::from(&v);
+        let (mut a, _): (Vec<_>, Vec<_>) = temp.iter_mut().partition(|_| true);
+        *a[0] = 100;
+        *a[1] = 200;
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_iter_mut_rfold_retains_wrappers() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut temp = CowVec::from(&v);
+        let _retained = temp.iter_mut().rfold(Vec::new(), |mut retained, item| {
+            retained.push(item);
+            retained
+        });
+    }
+
+    #[test]
+    fn test_lending_iter_mut() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut temp = CowVec::from(&v);
+        {
+            let mut iter = temp.lending_iter_mut();
+            let mut sum = 0;
+            while let Some(item) = iter.next() {
+                sum += *item;
+            }
+            assert_eq!(sum, 10);
+        }
+        assert!(!temp.is_owned());
+
+        {
+            let mut iter = temp.lending_iter_mut();
+            assert_eq!(iter.len(), 4);
+            {
+                let mut last = iter.next_back().unwrap();
+                *last = 40;
+            }
+            while let Some(mut item) = iter.next() {
+                if *item == 2 {
+                    *item = 20;
+                }
+                *item += 1;
+            }
+            assert!(iter.is_empty());
+        }
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![2, 21, 4, 40]);
+        assert_eq!(v, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_zero_size_lending_iter_mut() {
+        let v = vec![(), ()];
+        let mut temp = CowVec::from(&v);
+        let mut iter = temp.lending_iter_mut();
+        let mut count = 0;
+        while let Some(mut item) = iter.next() {
+            *item = ();
+            count += 1;
+        }
+        assert_eq!(count, 2);
+        assert!(temp.is_owned());
+    }
+
+    #[test]
+    fn test_iter_mut_range() {
+        let v = vec![1i32, 2, 3, 4, 5];
+        let mut temp = CowVec::from(&v);
+        let seen: Vec<i32> = temp.iter_mut_range(1..4).map(|item| *item).collect();
+        assert_eq!(seen, vec![2, 3, 4]);
+        assert_eq!(temp.iter_mut_range(2..=2).count(), 1);
+        assert_eq!(temp.iter_mut_range(5..).count(), 0);
+        assert!(!temp.is_owned());
+
+        {
+            let mut iter = temp.iter_mut_range(1..4);
+            {
+                let mut item = iter.next().unwrap();
+                *item = 20;
+            }
+            assert_eq!(*iter.next().unwrap(), 3);
+            {
+                let mut item = iter.next_back().unwrap();
+                *item = 40;
+            }
+            assert!(iter.next().is_none());
+        }
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![1, 20, 3, 40, 5]);
+
+        for mut item in temp.iter_mut_range(..2) {
+            *item += 100;
+        }
+        assert_eq!(*temp, vec![101, 120, 3, 40, 5]);
+        assert_eq!(v, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_iter_mut_range_out_of_bounds() {
+        let v = vec![1i32, 2, 3];
+        let mut temp = CowVec::from(&v);
+        let _ = temp.iter_mut_range(1..4);
+    }
+
+    #[test]
+    fn test_fast_for_each_range() {
+        let v = vec![1i32, 2, 3, 4, 5];
+        let mut temp = CowVec::from(&v);
+        let mut seen = Vec::new();
+        temp.fast_for_each_mut_range(3.., |item| seen.push(**item));
+        assert_eq!(seen, vec![4, 5]);
+        assert!(!temp.is_owned());
+
+        temp.fast_for_each_mut_range(1..3, |item| {
+            **item *= 10;
+        });
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![1, 20, 30, 4, 5]);
+
+        temp.fast_for_each_mut_range(..1, |item| {
+            **item = 0;
+        });
+        assert_eq!(*temp, vec![0, 20, 30, 4, 5]);
+        assert_eq!(v, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_zero_size_range() {
+        let v = vec![(), (), (), ()];
+        let mut temp = CowVec::from(&v);
+        assert_eq!(temp.iter_mut_range(1..3).count(), 2);
+        let mut count = 0;
+        temp.fast_for_each_mut_range(1.., |item| {
+            **item = ();
+            count += 1;
+        });
+        assert_eq!(count, 3);
+        assert_eq!(temp.len(), 4);
+    }
+
+    #[test]
+    fn test_fast_for_each_indexed() {
+        let v = vec![10i32, 11, 12, 13];
+        let mut temp = CowVec::from(&v);
+        let mut seen = Vec::new();
+        temp.fast_for_each_mut_indexed(|index, item| seen.push((index, **item)));
+        assert_eq!(seen, vec![(0, 10), (1, 11), (2, 12), (3, 13)]);
+        assert!(!temp.is_owned());
+
+        let mut seen = Vec::new();
+        temp.fast_for_each_mut_indexed(|index, item| {
+            seen.push(index);
+            if index == 1 {
+                **item = 0;
+            }
+        });
+        assert_eq!(seen, vec![0, 1, 2, 3]);
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![10, 0, 12, 13]);
+
+        temp.fast_for_each_mut_indexed(|index, item| {
+            **item += index as i32;
+        });
+        assert_eq!(*temp, vec![10, 1, 14, 16]);
+        assert_eq!(v, vec![10, 11, 12, 13]);
+    }
+
+    #[test]
+    fn test_zero_size_fast_for_each_indexed() {
+        let v = vec![(), (), ()];
+        let mut temp = CowVec::from(&v);
+        let mut seen = Vec::new();
+        temp.fast_for_each_mut_indexed(|index, item| {
+            if index == 1 {
+                **item = ();
+            }
+            seen.push(index);
+        });
+        assert_eq!(seen, vec![0, 1, 2]);
+        assert!(temp.is_owned());
+    }
+
+    #[test]
+    fn test_try_fast_for_each_control_flow() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut temp = CowVec::from(&v);
+        let mut visited = 0;
+        let result = temp.try_fast_for_each_mut(|item| {
+            visited += 1;
+            if **item == 2 {
+                ControlFlow::Break(**item)
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(result, ControlFlow::Break(2));
+        assert_eq!(visited, 2);
+        assert!(!temp.is_owned());
+
+        let result = temp.try_fast_for_each_mut(|item| {
+            if **item == 3 {
+                return ControlFlow::Break(());
+            }
+            **item *= 10;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(result, ControlFlow::Break(()));
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![10, 20, 3, 4]);
+
+        let result: ControlFlow<()> = temp.try_fast_for_each_mut(|item| {
+            **item += 1;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(result, ControlFlow::Continue(()));
+        assert_eq!(*temp, vec![11, 21, 4, 5]);
+        assert_eq!(v, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_try_fast_for_each_result() {
+        let v = vec![1i32, 2, -3, 4];
+        let mut temp = CowVec::from(&v);
+        let result = temp.try_fast_for_each_mut(|item| {
+            if **item < 0 {
+                Err(format!("negative value {}", **item))
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(result, Err("negative value -3".to_string()));
+        assert!(!temp.is_owned());
+
+        let result: Result<(), ()> = temp.try_fast_for_each_mut(|_item| Ok(()));
+        assert_eq!(result, Ok(()));
+        assert!(!temp.is_owned());
+    }
+
+    #[test]
+    fn test_zip_fast_for_each() {
+        let positions = vec![0i32, 10, 20];
+        let velocities = vec![1i32, 0, -1];
+        let mut pos = CowVec::from(&positions);
+        let mut vel = CowVec::from(&velocities);
+
+        pos.zip_fast_for_each_mut(&mut vel, |p, v| {
+            if **v != 0 {
+                **p += **v;
+            }
+        });
+        assert!(pos.is_owned());
+        assert!(!vel.is_owned());
+        assert_eq!(*pos, vec![1, 10, 19]);
+
+        let mut sum = 0;
+        pos.zip_fast_for_each_mut(&mut vel, |p, v| {
+            sum += **p * **v;
+            if **p == 10 {
+                **v = 5;
+            }
+        });
+        assert_eq!(sum, 1 - 19);
+        assert!(vel.is_owned());
+        assert_eq!(*vel, vec![1, 5, -1]);
+        assert_eq!(positions, vec![0, 10, 20]);
+        assert_eq!(velocities, vec![1, 0, -1]);
+    }
+
+    #[test]
+    fn test_zip_fast_for_each_mixed_types() {
+        let names = vec!["a", "b"];
+        let units = vec![(), ()];
+        let mut names = CowVec::from(&names);
+        let mut units = CowVec::from(&units);
+        let mut count = 0;
+        names.zip_fast_for_each_mut(&mut units, |name, unit| {
+            **unit = ();
+            if **name == "b" {
+                **name = "c";
+            }
+            count += 1;
+        });
+        assert_eq!(count, 2);
+        assert_eq!(*names, vec!["a", "c"]);
+        assert!(units.is_owned());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zip_fast_for_each_different_lengths() {
+        let a = vec![1i32, 2];
+        let b = vec![1i32];
+        let mut a = CowVec::from(&a);
+        let mut b = CowVec::from(&b);
+        a.zip_fast_for_each_mut(&mut b, |_, _| {});
+    }
+
+    #[test]
+    fn test_chunks_mut() {
+        let v = vec![1i32, 2, 3, 4, 5, 6, 7];
+        let mut temp = CowVec::from(&v);
+        let sums: Vec<i32> = temp.chunks_mut(3).map(|chunk| chunk.iter().sum()).collect();
+        assert_eq!(sums, vec![6, 15, 7]);
+        assert_eq!(temp.chunks_mut(3).len(), 3);
+        assert!(!temp.is_owned());
+
+        for mut chunk in temp.chunks_mut(2) {
+            if chunk[0] == 3 {
+                chunk.reverse();
+            }
+            if chunk[0] == 5 {
+                chunk[1] = 60;
+            }
+        }
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![1, 2, 4, 3, 5, 60, 7]);
+        assert_eq!(v, vec![1, 2, 3, 4, 5, 6, 7]);
+
+        let mut iter = temp.chunks_mut(4);
+        assert_eq!(iter.by_ref().count(), 2);
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_chunks_mut_retaining_chunk_causes_panic() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut temp = CowVec::from(&v);
+        let mut iter = temp.chunks_mut(2);
+        let _a = iter.next().unwrap();
+        let _b = iter.next().unwrap();
+    }
+
+    #[test]
+    fn test_zero_size_chunks_mut() {
+        let v = vec![(), (), ()];
+        let mut temp = CowVec::from(&v);
+        let lens: Vec<usize> = temp.chunks_mut(2).map(|chunk| chunk.len()).collect();
+        assert_eq!(lens, vec![2, 1]);
+        for mut chunk in temp.chunks_mut(2) {
+            chunk[0] = ();
+        }
+        assert!(temp.is_owned());
+        assert_eq!(temp.len(), 3);
+    }
+
+    #[test]
+    fn test_set_if_changed() {
+        let v = vec![1i32, 2, 3];
+        let mut temp = CowVec::from(&v);
+        assert!(!temp.set_if_changed(1, 2));
+        assert!(!temp.is_owned());
+        assert!(temp.set_if_changed(1, 20));
+        assert!(temp.is_owned());
+        assert!(!temp.set_if_changed(1, 20));
+        assert_eq!(*temp, vec![1, 20, 3]);
+        assert_eq!(v, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_apply_updates() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut temp = CowVec::from(&v);
+        assert_eq!(temp.apply_updates(vec![(0, 1), (3, 4)]), 0);
+        assert!(!temp.is_owned());
+
+        assert_eq!(temp.apply_updates(vec![(0, 1), (2, 30), (3, 4), (1, 20)]), 2);
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![1, 20, 30, 4]);
+        assert_eq!(v, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_set_if_changed_out_of_bounds() {
+        let v = vec![1i32];
+        let mut temp = CowVec::from(&v);
+        temp.set_if_changed(1, 1);
+    }
+
+    #[test]
+    fn test_speculative_for_each() {
+        fn clamp(x: &mut i32) {
+            *x = (*x).min(10);
+        }
+        let v = vec![1i32, 5, 10];
+        let mut temp = CowVec::from(&v);
+        temp.speculative_for_each_mut(clamp);
+        assert!(!temp.is_owned());
+
+        let v = vec![1i32, 50, 10, 70];
+        let mut temp = CowVec::from(&v);
+        let mut visited = 0;
+        temp.speculative_for_each_mut(|x| {
+            visited += 1;
+            clamp(x)
+        });
+        assert_eq!(visited, 4);
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![1, 10, 10, 10]);
+        assert_eq!(v, vec![1, 50, 10, 70]);
+        assert_eq!(temp.modified_indices().collect::<Vec<_>>(), vec![1, 3]);
+
+        temp.speculative_for_each_mut(|x| *x = (*x).max(10));
+        assert_eq!(*temp, vec![10, 10, 10, 10]);
+        assert_eq!(temp.modified_indices().collect::<Vec<_>>(), vec![0, 1, 3]);
+        temp.speculative_for_each_mut(|x| *x += 1);
+        assert_eq!(*temp, vec![11, 11, 11, 11]);
+    }
+
+    #[test]
+    fn test_map_in_place_lazy() {
+        let v = vec![1i32, -2, 3, -4];
+        let mut temp = CowVec::from(&v);
+        temp.map_in_place_lazy(|x| if *x > 10 { Some(10) } else { None });
+        assert!(!temp.is_owned());
+
+        let mut visited = Vec::new();
+        temp.map_in_place_lazy(|x| {
+            visited.push(*x);
+            if *x < 0 {
+                Some(-*x)
+            } else {
+                None
+            }
+        });
+        assert_eq!(visited, vec![1, -2, 3, -4]);
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![1, 2, 3, 4]);
+
+        temp.map_in_place_lazy(|x| if *x == 3 { Some(30) } else { None });
+        assert_eq!(*temp, vec![1, 2, 30, 4]);
+        assert_eq!(v, vec![1, -2, 3, -4]);
+    }
+
+    #[test]
+    fn test_retain_lazy() {
+        let v = vec![1i32, 2, 3, 4, 5];
+        let mut temp = CowVec::from(&v);
+        temp.retain_lazy(|x| *x < 10);
+        assert!(!temp.is_owned());
+        assert_eq!(*temp, v);
+
+        temp.retain_lazy(|x| *x % 2 == 1);
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![1, 3, 5]);
+
+        temp.retain_lazy(|x| *x != 3);
+        assert_eq!(*temp, vec![1, 5]);
+        assert_eq!(v, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_retain_lazy_clones_only_retained() {
+        let v: Vec<Counted> = (0..10).map(Counted).collect();
+        let mut temp = CowVec::from(&v);
+        temp.retain_lazy(|x| x.0 < 3);
+        assert_eq!(Counted::take_clones(), 3);
+        assert_eq!(temp.len(), 3);
+
+        let mut temp = CowVec::from(&v);
+        temp.retain_mut_lazy(|x| {
+            if x.0 == 8 {
+                x.0 = 80;
+            }
+            x.0 >= 7
+        });
+        assert_eq!(Counted::take_clones(), 3);
+        assert_eq!(temp.iter().map(|x| x.0).collect::<Vec<_>>(), vec![7, 80, 9]);
+    }
+
+    #[test]
+    fn test_retain_mut_lazy() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut temp = CowVec::from(&v);
+        temp.retain_mut_lazy(|x| **x > 0);
+        assert!(!temp.is_owned());
 
-        Some(retval)
+        temp.retain_mut_lazy(|x| {
+            if **x == 3 {
+                **x = 30;
+            }
+            true
+        });
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![1, 2, 30, 4]);
+
+        temp.retain_mut_lazy(|x| {
+            **x += 1;
+            **x != 3
+        });
+        assert_eq!(*temp, vec![2, 31, 5]);
+        assert_eq!(v, vec![1, 2, 3, 4]);
     }
-}
 
+    #[test]
+    fn test_truncate_and_pop_keep_borrowing() {
+        let v = vec![1i32, 2, 3, 4, 5];
+        let mut temp = CowVec::from(&v);
+        temp.truncate(10);
+        assert_eq!(temp.len(), 5);
+        temp.truncate(4);
+        assert_eq!(temp.pop(), Some(4));
+        assert!(!temp.is_owned());
+        assert_eq!(*temp, vec![1, 2, 3]);
+
+        for mut item in temp.iter_mut() {
+            if *item == 3 {
+                *item = 30;
+            }
+        }
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![1, 2, 30]);
+        temp.clear();
+        assert_eq!(temp.pop(), None);
+        assert_eq!(v, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_drain_prefix_and_suffix_keep_borrowing() {
+        let v = vec![1i32, 2, 3, 4, 5, 6];
+        let mut temp = CowVec::from(&v);
+        assert_eq!(temp.drain(..2).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(temp.drain(3..).rev().collect::<Vec<_>>(), vec![6]);
+        temp.drain(..0);
+        assert_eq!(temp.drain(1..1).count(), 0);
+        assert!(!temp.is_owned());
+        assert_eq!(*temp, vec![3, 4, 5]);
 
+        temp.fast_for_each_mut(|item| {
+            if **item == 4 {
+                **item = 40;
+            }
+        });
+        assert_eq!(*temp, vec![3, 40, 5]);
 
-#[cfg(test)]
-mod tests {
+        let mut temp = CowVec::from(&v);
+        assert_eq!(temp.drain(1..3).len(), 2);
+        assert!(temp.is_owned());
+        assert_eq!(*temp, vec![1, 4, 5, 6]);
+        assert_eq!(v, vec![1, 2, 3, 4, 5, 6]);
+    }
 
-    use super::CowVec;
-    use crate::CowVecItemWrapper;
-    use std::ops::{Deref, DerefMut};
+    #[test]
+    fn test_split_off_keeps_borrowing() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut temp = CowVec::from(&v);
+        let mut tail = temp.split_off(1);
+        assert!(!temp.is_owned());
+        assert!(!tail.is_owned());
+        assert_eq!(*temp, vec![1]);
+        assert_eq!(*tail, vec![2, 3, 4]);
+
+        for mut item in tail.iter_mut().rev() {
+            if *item == 3 {
+                *item = 30;
+            }
+        }
+        assert_eq!(*tail, vec![2, 30, 4]);
+        let last = tail.split_off(2);
+        assert!(last.is_owned());
+        assert_eq!(*last, vec![4]);
+        assert_eq!(*tail, vec![2, 30]);
+        assert_eq!(temp.split_off(1).len(), 0);
+    }
 
     #[test]
     #[should_panic]
-    fn test_ensure_retaining_iterated_value_causes_panic() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+    fn test_split_off_out_of_bounds() {
+        let v = vec![1i32, 2];
         let mut temp = CowVec::from(&v);
+        let _ = temp.split_off(3);
+    }
 
-        {
-            let mut iter = temp.iter_mut();
+    #[test]
+    fn test_vec_methods() {
+        let v = vec![1i32, 2];
+        let mut temp = CowVec::from(&v);
+        temp.push(3);
+        assert!(temp.is_owned());
+        temp.insert(0, 0);
+        temp.extend(vec![4, 5]);
+        assert_eq!(temp.remove(1), 1);
+        temp.to_mut().retain(|x| *x != 4);
+        assert_eq!(*temp, vec![0, 2, 3, 5]);
+        assert_eq!(v, vec![1, 2]);
+    }
 
-            let mut a1 = iter.next().unwrap();
-            let mut a2 = iter.next().unwrap();
+    #[test]
+    fn test_from_slice() {
+        let array = [1i32, 2, 3, 4];
+        let mut temp = CowVec::from_slice(&array[1..]);
+        assert_eq!(*temp, [2, 3, 4]);
+        temp.fast_for_each_mut(|item| {
+            if **item == 3 {
+                **item = 30;
+            }
+        });
+        assert!(temp.is_owned());
+        assert_eq!(temp.into_owned(), vec![2, 30, 4]);
 
-            let a1_mut = a1.deref_mut();
-            let a2_mut = a2.deref_mut();
-            *a1_mut += 1;
-            *a2_mut += 1;
+        let boxed: Box<[i32]> = vec![5, 6].into_boxed_slice();
+        let mut temp = CowVec::from_slice(&boxed);
+        for item in temp.iter_mut() {
+            assert!(*item >= 5);
         }
+        assert!(!temp.is_owned());
+        temp.push(7);
+        assert_eq!(*temp, [5, 6, 7]);
+        assert_eq!(array, [1, 2, 3, 4]);
+        assert_eq!(*boxed, [5, 6]);
     }
+
     #[test]
-    fn test_two_back_to_back_iter_mut_should_be_allowed() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+    fn test_streaming_iter_mut() {
+        let v = vec![1i32, 2, 3, 4, 5];
         let mut temp = CowVec::from(&v);
+        {
+            let mut iter = temp.streaming_iter_mut();
+            while let Some(item) = iter.next() {
+                assert!(*item > 0);
+            }
+        }
+        assert!(!temp.is_owned());
 
         {
-            let mut _iter = temp.iter_mut();
-            let mut _iter = temp.iter_mut();
+            let mut iter = temp.streaming_iter_mut();
+            while let Some(mut item) = iter.next() {
+                if *item == 2 {
+                    *item = 20;
+                }
+                if *item == 4 {
+                    *item += 40;
+                }
+            }
         }
-    }
-    #[test]
-    fn test_simultaneous_iter_allowed() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
-        let temp = CowVec::from(&v);
+        assert!(temp.is_owned());
+        assert_eq!(*temp, [1, 20, 3, 44, 5]);
 
         {
-            let mut iter_a = temp.iter();
-            let mut iter_b = temp.iter();
-            assert_eq!(*iter_a.next().unwrap(), *iter_b.next().unwrap());
+            let mut iter = temp.streaming_iter_mut();
+            while let Some(mut item) = iter.next() {
+                let value = *item;
+                item.set(value + 1);
+            }
         }
+        assert_eq!(*temp, [2, 21, 4, 45, 6]);
+        assert_eq!(v, vec![1, 2, 3, 4, 5]);
     }
+
     #[test]
-    fn test_cornercase1() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+    fn test_streaming_iter_mut_stopping_early() {
+        let v = vec![1i32, 2, 3, 4, 5];
         let mut temp = CowVec::from(&v);
-
         {
+            let mut iter = temp.streaming_iter_mut();
             {
-                let mut wrapper;
-                let mut iter_a = temp.iter_mut();
-                wrapper = iter_a.next().unwrap();
-                *wrapper = 73;
+                let mut item = iter.next().unwrap();
+                *item = 10;
             }
-            let mut iter_b = temp.iter_mut();
-            let _first = iter_b.next().unwrap();
+            iter.next().unwrap();
+            let mut item = iter.next().unwrap();
+            assert_eq!(*item, 3);
+            item.set(30);
         }
+        assert!(temp.is_owned());
+        assert_eq!(*temp, [10, 2, 30, 4, 5]);
     }
 
     #[test]
-    fn test_cornercase2() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+    fn test_streaming_iter_mut_clone_count() {
+        let v: Vec<Counted> = (0..10).map(Counted).collect();
         let mut temp = CowVec::from(&v);
-
         {
-            let mut iter_a;
-            {
-                let mut wrapper;
-                iter_a = temp.iter_mut();
-                wrapper = iter_a.next().unwrap();
-                *wrapper = 73;
+            let mut iter = temp.streaming_iter_mut();
+            while let Some(mut item) = iter.next() {
+                if item.0 >= 3 {
+                    let value = item.0 * 10;
+                    item.set(Counted(value));
+                }
+                if item.0 == 1 {
+                    item.0 = 100;
+                }
             }
-            let _x = iter_a;
         }
+        // Items 0 to 2 are cloned, the rest are only overwritten
+        assert_eq!(Counted::take_clones(), 3);
+        assert_eq!(
+            temp.iter().map(|x| x.0).collect::<Vec<_>>(),
+            vec![0, 100, 2, 30, 40, 50, 60, 70, 80, 90]
+        );
     }
 
     #[test]
-    fn test_cornercase3() {
-        let mut v = Vec::new();
+    fn test_modified_tracking() {
+        let v: Vec<i32> = (0..10).collect();
+        let mut temp = CowVec::from(&v);
+        for mut item in temp.iter_mut() {
+            if *item == 2 {
+                *item = 20;
+            }
+        }
+        temp.fast_for_each_mut(|item| {
+            if **item == 5 {
+                **item = 50;
+            }
+        });
+        temp[7] = 70;
+        assert_eq!(CowVec::from(&v).get_mut(10), None);
+        let mut unchanged = CowVec::from(&v);
+        assert_eq!(unchanged.get_mut(5..11), None);
+        assert!(!unchanged.is_owned());
+        *temp.get_mut(8).unwrap() = 80;
+        assert_eq!(temp.get_mut(10), None);
+        assert_eq!(temp.modified_indices().collect::<Vec<_>>(), vec![2, 5, 7, 8]);
+        assert_eq!(temp.modified_ranges().collect::<Vec<_>>(), vec![2..3, 5..6, 7..9]);
+        assert!(temp.is_modified(2));
+        assert!(!temp.is_modified(3));
+        assert!(!temp.is_modified(100));
+
+        temp.clear_modified();
         {
-            let mut temp;
-            v.push(32i32);
-            v.push(33i32);
-            temp = CowVec::from(&v);
-            let _val = temp.iter_mut().next().unwrap();
+            let mut iter = temp.lending_iter_mut();
+            iter.next();
+            *iter.next().unwrap() = 1;
         }
-        v.push(32);
+        for mut chunk in temp.chunks_mut(3) {
+            if chunk[0] == 3 {
+                chunk[1] = 4;
+            }
+        }
+        assert!(temp.set_if_changed(9, 90));
+        assert!(!temp.set_if_changed(0, 0));
+        assert_eq!(temp.modified_ranges().collect::<Vec<_>>(), vec![1..2, 3..6, 9..10]);
     }
 
     #[test]
-    fn test_ref_items() {
-        let mut v2 = Vec::new();
-        let mut temp;
-        {
-            let mut v = Vec::new();
-            v.push(32i32);
-            v.push(33i32);
-            v2.push(&v[0]);
-            v2.push(&v[1]);
-
-            temp = CowVec::from(&v2);
-            temp.push(&37);
-        }
+    fn test_modified_tracking_structural_changes() {
+        let v: Vec<i32> = (0..6).collect();
+        let mut temp = CowVec::from(&v);
+        temp.truncate(5);
+        assert_eq!(temp.modified_indices().count(), 0);
+        temp[1] = 10;
+        temp[3] = 30;
+        temp.insert(0, -1);
+        assert_eq!(temp.modified_indices().collect::<Vec<_>>(), vec![0, 2, 4]);
+        temp.remove(1);
+        assert_eq!(temp.modified_indices().collect::<Vec<_>>(), vec![0, 1, 3]);
+        temp.push(5);
+        temp.extend(vec![6, 7]);
+        assert_eq!(temp.modified_ranges().collect::<Vec<_>>(), vec![0..2, 3..4, 5..8]);
+        temp.drain(1..3);
+        assert_eq!(*temp, [-1, 30, 4, 5, 6, 7]);
+        assert_eq!(temp.modified_ranges().collect::<Vec<_>>(), vec![0..2, 3..6]);
+        temp.retain_lazy(|x| *x != 4);
+        assert_eq!(temp.modified_ranges().collect::<Vec<_>>(), vec![0..5]);
+        let tail = temp.split_off(3);
+        assert_eq!(tail.modified_indices().collect::<Vec<_>>(), vec![0, 1]);
+        temp.clear_modified();
+        temp.retain_mut_lazy(|item| {
+            if **item == 30 {
+                **item = 31;
+            }
+            **item != -1
+        });
+        assert_eq!(*temp, [31, 5]);
+        assert_eq!(temp.modified_indices().collect::<Vec<_>>(), vec![0]);
+        temp.pop();
+        assert_eq!(temp.modified_indices().collect::<Vec<_>>(), vec![0]);
     }
 
     #[test]
-    fn test_with_empty_vec() {
-        let primary: Vec<i32> = vec![];
-        let mut cowvec = CowVec::from(&primary);
+    fn test_modified_tracking_untracked_writes() {
+        let v = vec![1i32, 2, 3];
+        let mut temp = CowVec::from(&v);
+        temp.retain_mut_lazy(|item| {
+            if **item == 2 {
+                **item = 20;
+            }
+            true
+        });
+        assert_eq!(temp.modified_indices().collect::<Vec<_>>(), vec![1]);
+        temp.map_in_place_lazy(|x| if *x == 3 { Some(30) } else { None });
+        assert_eq!(temp.modified_indices().collect::<Vec<_>>(), vec![1, 2]);
 
-        cowvec.ensure_owned();
-        assert_eq!(cowvec.len(), 0);
-        let _ = cowvec.to_owned();
+        temp.to_mut().push(4);
+        assert_eq!(temp.modified_ranges().collect::<Vec<_>>(), vec![0..4]);
+        temp.clear_modified();
+        temp.sort();
+        assert_eq!(temp.modified_ranges().collect::<Vec<_>>(), vec![0..4]);
+
+        let v = vec![(); 4];
+        let mut temp = CowVec::from(&v);
+        temp.fast_for_each_mut_indexed(|index, item| {
+            if index == 2 {
+                **item = ();
+            }
+        });
+        assert_eq!(temp.modified_indices().collect::<Vec<_>>(), vec![2]);
+        temp[1] = ();
+        assert_eq!(temp.modified_indices().count(), 4);
     }
 
     #[test]
-    fn test_eager_cloned_iter_mut() {
-        let mut temp = CowVec::from_owned(vec![1, 2, 3]);
-        let output: Vec<_> = temp.eager_cloned_iter_mut().collect();
-        assert_eq!(*output[0], 1);
-        assert_eq!(*output[1], 2);
-        assert_eq!(*output[2], 3);
+    fn test_diff() {
+        let v: Vec<i32> = (0..10).collect();
+        let mut temp = CowVec::from(&v);
+        assert!(temp.diff().is_empty());
+        temp.drain(..2);
+        temp.pop();
+        assert!(!temp.is_owned());
+        assert_eq!(
+            temp.diff().ops(),
+            &[
+                PatchOp::Remove { index: 0, len: 2 },
+                PatchOp::Remove { index: 9, len: 1 },
+            ]
+        );
+
+        temp[3] = 50;
+        temp.insert(0, -1);
+        let patch = temp.diff();
+        assert_eq!(
+            patch.ops(),
+            &[
+                PatchOp::Replace {
+                    index: 0,
+                    values: vec![-1]
+                },
+                PatchOp::Remove { index: 1, len: 1 },
+                PatchOp::Replace {
+                    index: 5,
+                    values: vec![50]
+                },
+                PatchOp::Remove { index: 9, len: 1 },
+            ]
+        );
+        assert_eq!(patch.apply_to(&v), *temp);
+
+        let temp = CowVec::from_owned(vec![1, 2]);
+        assert_eq!(
+            temp.diff().ops(),
+            &[PatchOp::Insert {
+                index: 0,
+                values: vec![1, 2]
+            }]
+        );
     }
+
     #[test]
-    fn test_basics1() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+    fn test_revert() {
+        let v: Vec<i32> = (0..6).collect();
         let mut temp = CowVec::from(&v);
-
-        {
-            let temp2 = &mut temp;
-            {
-                let mut iter = temp2.iter_mut();
-
-                assert_eq!(*iter.next().unwrap(), 32);
-                assert_eq!(*iter.next().unwrap(), 33);
-            }
-        }
-        {
-            let mut iter = temp.iter_mut();
-            assert_eq!(*iter.next().unwrap(), 32);
-        }
-        assert_eq!(temp.is_owned(), false);
+        temp.fast_for_each_mut(|item| **item *= 10);
+        temp.pop();
+        assert_eq!(temp.original(), &v[..]);
+        temp.revert(1);
+        temp.revert_range(3..);
+        assert_eq!(*temp, [0, 1, 20, 3, 4]);
+        assert_eq!(temp.modified_indices().collect::<Vec<_>>(), vec![0, 2]);
+        temp.push(60);
+        temp.reset();
+        assert!(!temp.is_owned());
+        assert_eq!(*temp, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(temp.modified_indices().count(), 0);
+
+        temp.truncate(2);
+        temp.revert_range(..);
+        assert!(!temp.is_owned());
+        assert_eq!(CowVec::from_owned(vec![1]).original(), &[] as &[i32]);
     }
 
     #[test]
-    fn test_iter_nth() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+    fn test_reset_without_original() {
+        let mut temp = CowVec::from_owned(vec![1i32, 2]);
+        temp.push(3);
+        temp.reset();
+        assert_eq!(*temp, [1, 2, 3]);
+        assert!(!temp.try_reborrow());
+
+        let empty: Vec<i32> = Vec::new();
+        let mut temp = CowVec::from(&empty);
+        temp.push(1);
+        temp.reset();
+        assert!(temp.is_empty());
+        assert!(!temp.is_owned());
+    }
 
-        let mut i = v.iter();
-        i.next().unwrap();
+    #[test]
+    fn test_split_off_original() {
+        let v = vec![1i32, 2, 3, 4];
+        let mut borrowed = CowVec::from(&v);
+        let mut owned = CowVec::from(&v);
+        owned[3] = 40;
+        let borrowed_tail = borrowed.split_off(2);
+        let mut owned_tail = owned.split_off(2);
+        assert_eq!(borrowed_tail.original(), &[3, 4]);
+        assert_eq!(owned_tail.original(), &[3, 4]);
+        assert_eq!(
+            owned_tail.diff().ops(),
+            &[PatchOp::Replace {
+                index: 1,
+                values: vec![40]
+            }]
+        );
+        assert!(borrowed_tail.diff().is_empty());
+        owned_tail.reset();
+        assert_eq!(*owned_tail, [3, 4]);
+
+        let mut shifted = CowVec::from(&v);
+        shifted.remove(0);
+        let shifted_tail = shifted.split_off(1);
+        assert_eq!(shifted_tail.original(), &[] as &[i32]);
+        assert_eq!(shifted_tail.diff().apply_to(&[]), vec![3, 4]);
+    }
 
-        assert_eq!(*i.nth(0).unwrap(), 33);
-        assert_eq!(i.nth(1), None);
+    #[test]
+    fn test_revert_after_clear_modified() {
+        let v = vec![1i32, 2, 3];
+        let mut temp = CowVec::from(&v);
+        temp[0] = 10;
+        temp.clear_modified();
+        temp.revert(0);
+        assert_eq!(*temp, [1, 2, 3]);
     }
 
     #[test]
-    fn test_iter_nth2() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
-        v.push(34i32);
-        v.push(35i32);
+    #[should_panic]
+    fn test_revert_after_insert() {
+        let v = vec![1i32, 2, 3];
+        let mut temp = CowVec::from(&v);
+        temp.insert(0, 0);
+        temp.revert(1);
+    }
 
-        let mut cowvec = CowVec::from(&v);
+    #[test]
+    #[should_panic]
+    fn test_revert_appended_item() {
+        let v = vec![1i32, 2, 3];
+        let mut temp = CowVec::from(&v);
+        temp.push(4);
+        temp.revert(3);
+    }
 
-        let mut i = cowvec.iter_mut();
-        let v0 = i.nth(0).unwrap();
-        assert_eq!(*v0, 32);
-        let v2 = i.nth(1).unwrap();
-        assert_eq!(*v2, 34);
-        let v3 = i.nth(0).unwrap();
-        assert_eq!(*v3, 35);
-        let v4 = i.nth(1);
-        assert!(v4.is_none());
+    #[test]
+    fn test_try_reborrow() {
+        let v = vec![1i32, 2, 3];
+        let mut temp = CowVec::from(&v);
+        assert!(!temp.try_reborrow());
+        temp[1] = 20;
+        assert!(!temp.try_reborrow());
+        temp[1] = 2;
+        assert!(temp.try_reborrow());
+        assert!(!temp.is_owned());
+        assert_eq!(temp.modified_indices().count(), 0);
+        assert_eq!(*temp, [1, 2, 3]);
+
+        temp.push(4);
+        temp.pop();
+        assert!(temp.try_reborrow());
+        temp.truncate(2);
+        temp.push(3);
+        assert!(temp.try_reborrow());
+        assert!(!CowVec::from_owned(vec![1]).try_reborrow());
     }
 
     #[test]
-    fn test_iter_count_and_size_hint() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+    fn test_into_changed() {
+        let v = vec![1i32, 2, 3];
+        assert_eq!(CowVec::from(&v).into_changed(), None);
         let mut temp = CowVec::from(&v);
-        assert_eq!(temp.iter_mut().count(), 2);
-        let mut it = temp.iter_mut();
-        it.next().unwrap();
-        assert_eq!(it.size_hint().0, 1);
-        assert_eq!(it.size_hint().1, Some(1));
-        assert_eq!(it.count(), 1);
-        let mut it = temp.iter_mut();
-        it.next().unwrap();
-        it.next().unwrap();
-        assert_eq!(it.count(), 0);
+        temp.truncate(2);
+        assert_eq!(temp.into_changed(), Some(vec![1, 2]));
+
+        let mut temp = CowVec::from(&v);
+        temp[0] = 10;
+        temp[0] = 1;
+        assert_eq!(temp.into_changed(), Some(vec![1, 2, 3]));
+
+        let mut temp = CowVec::from(&v);
+        temp.set_auto_reborrow(true);
+        temp[0] = 10;
+        temp[0] = 1;
+        let mut tail = temp.split_off(1);
+        assert_eq!(temp.into_changed(), Some(vec![1]));
+        tail[0] = 2;
+        assert_eq!(tail.into_changed(), Some(vec![2, 3]));
+
+        let mut temp = CowVec::from(&v);
+        temp.set_auto_reborrow(true);
+        temp.fast_for_each_mut(|item| **item += 1);
+        temp.fast_for_each_mut(|item| **item -= 1);
+        assert!(temp.is_owned());
+        assert_eq!(temp.into_changed(), None);
     }
+
     #[test]
     fn test_zero_size_iter_mut() {
-        let mut v = Vec::new();
-        v.push(());
-        v.push(());
+        let v = vec![(), ()];
 
         let mut temp = CowVec::from(&v);
 
@@ -836,9 +3488,7 @@ mod tests {
 
     #[test]
     fn test_zero_size_for_each() {
-        let mut v = Vec::new();
-        v.push(());
-        v.push(());
+        let v = vec![(), ()];
 
         let mut temp = CowVec::from(&v);
 
@@ -853,9 +3503,7 @@ mod tests {
 
     #[test]
     fn test_zero_size_for_each2() {
-        let mut v = Vec::new();
-        v.push(());
-        v.push(());
+        let v = vec![(), ()];
 
         let mut temp = CowVec::from(&v);
 
@@ -873,9 +3521,7 @@ mod tests {
     }
     #[test]
     fn test_zero_size_for_fast_each() {
-        let mut v = Vec::new();
-        v.push(());
-        v.push(());
+        let v = vec![(), ()];
 
         let mut temp = CowVec::from(&v);
 
@@ -890,9 +3536,7 @@ mod tests {
 
     #[test]
     fn test_for_each_owning() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+        let v = vec![32i32, 33i32];
 
         let mut temp = CowVec::from(&v);
         temp.iter_mut().for_each(|mut item| {
@@ -909,9 +3553,7 @@ mod tests {
     }
     #[test]
     fn test_for_each_not_always_owning() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+        let v = vec![32i32, 33i32];
 
         let mut temp = CowVec::from(&v);
         temp.iter_mut().for_each(|mut item| {
@@ -919,16 +3561,14 @@ mod tests {
                 *item = 47;
             }
         });
-        assert_eq!(temp.is_owned(), false);
+        assert!(!temp.is_owned());
         let result = temp.to_owned();
         assert_eq!(result[0], 32);
         assert_eq!(result[1], 33);
     }
     #[test]
     fn test_mut_twice() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+        let v = vec![32i32, 33i32];
         let mut temp = CowVec::from(&v);
         let mut iter = temp.iter_mut();
 
@@ -943,19 +3583,17 @@ mod tests {
     }
     #[test]
     fn test_fast_for_each() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+        let v = vec![32i32, 33i32];
         let mut temp = CowVec::from(&v);
         temp.fast_for_each_mut(|_item| {});
-        assert_eq!(temp.is_owned(), false);
+        assert!(!temp.is_owned());
 
         temp.fast_for_each_mut(|item| {
             if **item == 33 {
                 **item = 47;
             }
         });
-        assert_eq!(temp.is_owned(), true);
+        assert!(temp.is_owned());
 
         assert_eq!(temp[0], 32);
         assert_eq!(temp[1], 47);
@@ -964,12 +3602,10 @@ mod tests {
     }
     #[test]
     fn test_fast_for_each2() {
-        let mut v = Vec::new();
-        v.push(32i32);
-        v.push(33i32);
+        let v = vec![32i32, 33i32];
         let mut temp = CowVec::from(&v);
         temp.fast_for_each_mut(|_item| {});
-        assert_eq!(temp.is_owned(), false);
+        assert!(!temp.is_owned());
 
         temp.fast_for_each_mut(|item| {
             if **item == 32 {
@@ -991,7 +3627,7 @@ mod tests {
                 **item = 49;
             }
         });
-        assert_eq!(temp.is_owned(), true);
+        assert!(temp.is_owned());
 
         assert_eq!(temp[0], 47);
         assert_eq!(temp[1], 48);
@@ -1003,12 +3639,12 @@ mod tests {
         let v = Vec::new();
         let mut temp = CowVec::from(&v);
         temp.fast_for_each_mut(|_item| {});
-        assert_eq!(temp.is_owned(), false);
+        assert!(!temp.is_owned());
 
         temp.fast_for_each_mut(|item| {
             **item = 1;
         });
-        assert_eq!(temp.is_owned(), false);
+        assert!(!temp.is_owned());
     }
     #[test]
     #[cfg(not(miri))]
@@ -1056,18 +3692,46 @@ mod tests {
                     assert_eq!(*item, *reference);
                 }
             }
+
+            for _ in 0..gen_u32() % 3 {
+                match gen_u32() % 4 {
+                    0 => {
+                        let value = gen_u32();
+                        temp.push(value);
+                        clone.push(value);
+                    }
+                    1 => {
+                        assert_eq!(temp.pop(), clone.pop());
+                    }
+                    2 if !clone.is_empty() => {
+                        let index = gen_u32() as usize % clone.len();
+                        assert_eq!(temp.remove(index), clone.remove(index));
+                    }
+                    _ => {
+                        let index = gen_u32() as usize % (clone.len() + 1);
+                        let value = gen_u32();
+                        temp.insert(index, value);
+                        clone.insert(index, value);
+                    }
+                }
+            }
+            assert_eq!(*temp, clone[..]);
+
+            let patch = temp.diff();
+            assert_eq!(patch.apply_to(&v), clone);
+            let mut replica = v.clone();
+            patch.apply(&mut replica);
+            assert_eq!(replica, clone);
         }
     }
 
     #[test]
     fn test_taking_ownership() {
-        let mut v = Vec::new();
-        v.push(1);
-        v.push(2);
-        assert_eq!(*v.get(0).unwrap(), 1);
+        let v = vec![1, 2];
+        assert_eq!(*v.first().unwrap(), 1);
         assert_eq!(*v.get(1).unwrap(), 2);
         let mut temp = CowVec::from(&v);
-        assert_eq!(temp.is_owned(), false);
+        assert!(!temp.is_owned());
 
         {
             let mut _it = temp.iter_mut();
@@ -1080,8 +3744,8 @@ mod tests {
             }
             let mut _it = temp.iter_mut();
         }
-        assert_eq!(temp.is_owned(), true);
-        assert_eq!(*v.get(0).unwrap(), 1);
+        assert!(temp.is_owned());
+        assert_eq!(*v.first().unwrap(), 1);
         assert_eq!(*v.get(1).unwrap(), 2);
 
         {
@@ -1091,11 +3755,11 @@ mod tests {
 
             *x1.deref_mut() = 3;
         }
-        assert_eq!(temp.is_owned(), true);
+        assert!(temp.is_owned());
 
         assert_eq!(temp[0], 3);
         assert_eq!(temp[1], 4);
-        assert_eq!(*v.get(0).unwrap(), 1);
+        assert_eq!(*v.first().unwrap(), 1);
         assert_eq!(*v.get(1).unwrap(), 2);
     }
 
@@ -1109,10 +3773,7 @@ mod tests {
     #[bench]
     #[cfg(not(miri))]
     fn bench_cowvec(b: &mut Bencher) {
-        let mut thevec2 = Vec::new();
-        for _ in 0..ITERATIONS {
-            thevec2.push(32i128);
-        }
+        let thevec2 = vec![32i128; ITERATIONS];
         let mut thevec = CowVec::from(&thevec2);
 
         b.iter(|| {
@@ -1127,10 +3788,7 @@ mod tests {
     #[bench]
     #[cfg(not(miri))]
     fn bench_cowvec_eager_iter_mut(b: &mut Bencher) {
-        let mut thevec2 = Vec::new();
-        for _ in 0..ITERATIONS {
-            thevec2.push(32i128);
-        }
+        let thevec2 = vec![32i128; ITERATIONS];
         let mut thevec = CowVec::from(&thevec2);
 
         b.iter(|| {
@@ -1144,10 +3802,7 @@ mod tests {
     #[bench]
     #[cfg(not(miri))]
     fn bench_cowvec_for_each(b: &mut Bencher) {
-        let mut thevec2 = Vec::new();
-        for _ in 0..ITERATIONS {
-            thevec2.push(32i128);
-        }
+        let thevec2 = vec![32i128; ITERATIONS];
         let mut thevec = CowVec::from(&thevec2);
 
         b.iter(|| {
@@ -1161,10 +3816,7 @@ mod tests {
     #[bench]
     #[cfg(not(miri))]
     fn bench_cowvec_fast_for_each(b: &mut Bencher) {
-        let mut thevec2 = Vec::new();
-        for _ in 0..ITERATIONS {
-            thevec2.push(32i128);
-        }
+        let thevec2 = vec![32i128; ITERATIONS];
         let mut thevec = CowVec::from(&thevec2);
 
         b.iter(|| {
@@ -1177,11 +3829,22 @@ mod tests {
     }
     #[bench]
     #[cfg(not(miri))]
+    fn bench_cowvec_fast_for_each_indexed(b: &mut Bencher) {
+        let thevec2 = vec![32i128; ITERATIONS];
+        let mut thevec = CowVec::from(&thevec2);
+
+        b.iter(|| {
+            let mut sum = 0;
+            thevec.fast_for_each_mut_indexed(|index, item| {
+                sum += **item + index as i128;
+            });
+            sum
+        });
+    }
+    #[bench]
+    #[cfg(not(miri))]
     fn bench_cowvec_fast_for_each_owned_case(b: &mut Bencher) {
-        let mut thevec2 = Vec::new();
-        for _ in 0..ITERATIONS {
-            thevec2.push(32i128);
-        }
+        let thevec2 = vec![32i128; ITERATIONS];
         let mut thevec = CowVec::from(&thevec2);
 
         thevec.ensure_owned();
@@ -1196,10 +3859,7 @@ mod tests {
     #[bench]
     #[cfg(not(miri))]
     fn bench_vec(b: &mut Bencher) {
-        let mut thevec = Vec::new();
-        for _ in 0..ITERATIONS {
-            thevec.push(32i128);
-        }
+        let mut thevec = vec![32i128; ITERATIONS];
 
         b.iter(|| {
             let mut sum = 0;
@@ -1212,10 +3872,7 @@ mod tests {
     #[bench]
     #[cfg(not(miri))]
     fn bench_vec_clone(b: &mut Bencher) {
-        let mut thevec = Vec::new();
-        for _ in 0..ITERATIONS {
-            thevec.push(32i128);
-        }
+        let thevec = vec![32i128; ITERATIONS];
 
         b.iter(|| {
             let mut sum = 0;
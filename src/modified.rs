@@ -0,0 +1,281 @@
+//! Tracking of which items of a [CowVec](crate::CowVec) have been written.
+
+use std::iter::Peekable;
+use std::ops::Range;
+
+const WORD_BITS: usize = 64;
+
+/// A set of modified indices, stored as a bitset.
+///
+/// If 'all' is set, every index is considered modified. This is used when a mutable
+/// reference to the entire contents has been handed out, so that the actual writes
+/// cannot be observed.
+///
+/// If 'shifted' is set, the indices no longer match the positions of the items in the
+/// original Vec, since items have been inserted or removed before the end.
+#[derive(Clone, Default)]
+pub(crate) struct ModifiedSet {
+    words: Vec<u64>,
+    all: bool,
+    shifted: bool,
+}
+
+impl ModifiedSet {
+    pub(crate) fn new() -> ModifiedSet {
+        ModifiedSet::default()
+    }
+
+    pub(crate) fn insert(&mut self, index: usize) {
+        let word = index / WORD_BITS;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (index % WORD_BITS);
+    }
+    pub(crate) fn insert_range(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        let first_word = start / WORD_BITS;
+        let last_word = (end - 1) / WORD_BITS;
+        if last_word >= self.words.len() {
+            self.words.resize(last_word + 1, 0);
+        }
+        for word in first_word..=last_word {
+            let mut mask = !0u64;
+            if word == first_word {
+                mask &= !0u64 << (start % WORD_BITS);
+            }
+            if word == last_word {
+                mask &= !0u64 >> (WORD_BITS - 1 - (end - 1) % WORD_BITS);
+            }
+            self.words[word] |= mask;
+        }
+    }
+    /// Consider every index modified, until clear is called.
+    pub(crate) fn insert_all(&mut self) {
+        self.all = true;
+    }
+    pub(crate) fn contains(&self, index: usize) -> bool {
+        self.all
+            || self
+                .words
+                .get(index / WORD_BITS)
+                .is_some_and(|word| word & (1 << (index % WORD_BITS)) != 0)
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        !self.all && self.words.iter().all(|&word| word == 0)
+    }
+    pub(crate) fn clear(&mut self) {
+        self.words.clear();
+        self.all = false;
+    }
+    /// Record that the indices no longer match the positions in the original Vec.
+    pub(crate) fn set_shifted(&mut self) {
+        self.shifted = true;
+    }
+    /// Returns true if the indices may no longer match the positions in the original Vec.
+    pub(crate) fn shifted(&self) -> bool {
+        self.shifted
+    }
+    pub(crate) fn remove(&mut self, index: usize) {
+        if let Some(word) = self.words.get_mut(index / WORD_BITS) {
+            *word &= !(1 << (index % WORD_BITS));
+        }
+    }
+
+    /// Remove the indices in start..start + removed, and then make room for
+    /// 'inserted' unmodified indices at start. Indices after the removed ones are
+    /// moved accordingly. This mirrors what Vec::splice does to the items.
+    pub(crate) fn splice(&mut self, start: usize, removed: usize, inserted: usize) {
+        if removed != inserted {
+            self.shifted = true;
+        }
+        if self.all || removed == inserted {
+            if !self.all {
+                for index in start..start + removed {
+                    self.remove(index);
+                }
+            }
+            return;
+        }
+        let moved: Vec<usize> = self.indices(usize::MAX).filter(|&i| i >= start).collect();
+        self.truncate(start);
+        for index in moved {
+            if index >= start + removed {
+                self.insert(index - removed + inserted);
+            }
+        }
+    }
+    /// Forget all indices at or above len.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        if self.all {
+            return;
+        }
+        let words = len.div_ceil(WORD_BITS);
+        self.words.truncate(words);
+        let unused_bits = words * WORD_BITS - len;
+        if unused_bits > 0 && self.words.len() == words {
+            self.words[words - 1] &= !0 >> unused_bits;
+        }
+    }
+    /// Split the set at the given index, returning the indices from 'at' onwards
+    /// moved down by 'at'.
+    pub(crate) fn split_off(&mut self, at: usize) -> ModifiedSet {
+        let mut tail = ModifiedSet {
+            words: Vec::new(),
+            all: self.all,
+            shifted: self.shifted,
+        };
+        if !self.all {
+            for index in self.indices(usize::MAX).filter(|&i| i >= at) {
+                tail.insert(index - at);
+            }
+            self.truncate(at);
+        }
+        tail
+    }
+
+    /// Iterate over the modified indices below len, in order.
+    pub(crate) fn indices(&self, len: usize) -> ModifiedIndices<'_> {
+        ModifiedIndices {
+            set: self,
+            len,
+            word_index: 0,
+            bits: self.word(0),
+        }
+    }
+    /// Iterate over the modified indices below len, as ranges of consecutive indices.
+    pub(crate) fn ranges(&self, len: usize) -> ModifiedRanges<'_> {
+        ModifiedRanges {
+            indices: self.indices(len).peekable(),
+        }
+    }
+
+    fn word(&self, word_index: usize) -> u64 {
+        if self.all {
+            !0
+        } else {
+            self.words.get(word_index).copied().unwrap_or(0)
+        }
+    }
+}
+
+/// Iterator over the indices in a ModifiedSet.
+pub(crate) struct ModifiedIndices<'a> {
+    set: &'a ModifiedSet,
+    len: usize,
+    word_index: usize,
+    // The bits of the current word which have not been returned yet
+    bits: u64,
+}
+
+impl<'a> Iterator for ModifiedIndices<'a> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        loop {
+            if self.word_index > self.len / WORD_BITS {
+                return None;
+            }
+            if !self.set.all && self.word_index >= self.set.words.len() {
+                return None;
+            }
+            if self.bits != 0 {
+                let index = self.word_index * WORD_BITS + self.bits.trailing_zeros() as usize;
+                if index >= self.len {
+                    return None;
+                }
+                self.bits &= self.bits - 1;
+                return Some(index);
+            }
+            self.word_index += 1;
+            self.bits = self.set.word(self.word_index);
+        }
+    }
+}
+
+/// Iterator over the ranges of consecutive indices in a ModifiedSet.
+pub(crate) struct ModifiedRanges<'a> {
+    indices: Peekable<ModifiedIndices<'a>>,
+}
+
+impl<'a> Iterator for ModifiedRanges<'a> {
+    type Item = Range<usize>;
+    fn next(&mut self) -> Option<Range<usize>> {
+        let start = self.indices.next()?;
+        let mut end = start + 1;
+        while self.indices.peek() == Some(&end) {
+            self.indices.next();
+            end += 1;
+        }
+        Some(start..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ModifiedSet;
+
+    #[test]
+    fn test_modified_set_ranges() {
+        let mut set = ModifiedSet::new();
+        set.insert(3);
+        set.insert_range(5, 8);
+        set.insert(63);
+        set.insert(64);
+        set.insert(200);
+        assert_eq!(
+            set.indices(1000).collect::<Vec<_>>(),
+            vec![3, 5, 6, 7, 63, 64, 200]
+        );
+        assert_eq!(
+            set.ranges(1000).collect::<Vec<_>>(),
+            vec![3..4, 5..8, 63..65, 200..201]
+        );
+        assert_eq!(set.indices(64).collect::<Vec<_>>(), vec![3, 5, 6, 7, 63]);
+
+        set.insert_all();
+        assert_eq!(set.ranges(10).collect::<Vec<_>>(), vec![0..10]);
+        assert_eq!(set.indices(0).count(), 0);
+        set.clear();
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn test_modified_set_insert_range_across_words() {
+        let mut set = ModifiedSet::new();
+        set.insert_range(60, 200);
+        set.insert_range(256, 320);
+        set.insert_range(10, 10);
+        assert_eq!(
+            set.ranges(1000).collect::<Vec<_>>(),
+            vec![60..200, 256..320]
+        );
+        set.insert_range(0, 1);
+        set.insert_range(63, 64);
+        assert_eq!(
+            set.ranges(1000).collect::<Vec<_>>(),
+            vec![0..1, 60..200, 256..320]
+        );
+    }
+
+    #[test]
+    fn test_modified_set_splice() {
+        let mut set = ModifiedSet::new();
+        set.insert_range(0, 2);
+        set.insert(5);
+        set.insert(70);
+        set.splice(1, 2, 0);
+        assert_eq!(set.indices(1000).collect::<Vec<_>>(), vec![0, 3, 68]);
+        set.splice(1, 0, 1);
+        assert_eq!(set.indices(1000).collect::<Vec<_>>(), vec![0, 4, 69]);
+
+        let tail = set.split_off(4);
+        assert_eq!(set.indices(1000).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(tail.indices(1000).collect::<Vec<_>>(), vec![0, 65]);
+
+        let mut set = tail;
+        set.truncate(65);
+        assert_eq!(set.indices(1000).collect::<Vec<_>>(), vec![0]);
+    }
+}
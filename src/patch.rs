@@ -0,0 +1,374 @@
+//! Patches describing the changes made through a [CowVec](crate::CowVec).
+
+/// A single change in a [Patch](crate::Patch). All indices refer to positions in the
+/// original items, before any change of the patch has been applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PatchOp<T> {
+    /// Replace the original items starting at index with the given values.
+    Replace {
+        /// Index of the first replaced item.
+        index: usize,
+        /// The new values, one for each replaced item.
+        values: Vec<T>,
+    },
+    /// Insert the given values before the original item at index.
+    Insert {
+        /// Index of the original item before which the values are inserted. This may be
+        /// equal to the original length, to append.
+        index: usize,
+        /// The inserted values.
+        values: Vec<T>,
+    },
+    /// Remove len original items starting at index.
+    Remove {
+        /// Index of the first removed item.
+        index: usize,
+        /// The number of removed items.
+        len: usize,
+    },
+}
+
+/// The changes made to a borrowed slice, as returned by [CowVec::diff](crate::CowVec::diff).
+///
+/// The changes are sorted by index and do not overlap. A patch can be applied to any Vec
+/// with the same contents as the original, for example to a replica on another machine.
+///
+/// ```
+/// # use cow_vec_item::{CowVec, PatchOp};
+/// let original = vec![1, 2, 3, 4];
+/// let mut copy_on_write_ref = CowVec::from(&original);
+/// copy_on_write_ref[1] = 20;
+/// copy_on_write_ref.push(5);
+///
+/// let patch = copy_on_write_ref.diff();
+/// assert_eq!(
+///     patch.ops(),
+///     &[
+///         PatchOp::Replace { index: 1, values: vec![20] },
+///         PatchOp::Insert { index: 4, values: vec![5] },
+///     ]
+/// );
+///
+/// let mut replica = original.clone();
+/// patch.apply(&mut replica);
+/// assert_eq!(replica, vec![1, 20, 3, 4, 5]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Patch<T> {
+    ops: Vec<PatchOp<T>>,
+}
+
+impl<T: Clone> Patch<T> {
+    /// The changes, sorted by index.
+    pub fn ops(&self) -> &[PatchOp<T>] {
+        &self.ops
+    }
+    /// Returns true if the patch does not change anything.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+    /// Consumes the patch, returning the changes.
+    pub fn into_ops(self) -> Vec<PatchOp<T>> {
+        self.ops
+    }
+
+    /// Apply the changes to the given Vec, which must have the same contents as the
+    /// original items the patch was created from.
+    ///
+    /// Panics if an index of the patch is out of bounds for the Vec.
+    pub fn apply(&self, vec: &mut Vec<T>) {
+        // Going backwards, the original indices stay valid.
+        for op in self.ops.iter().rev() {
+            match op {
+                PatchOp::Replace { index, values } => {
+                    vec[*index..*index + values.len()].clone_from_slice(values);
+                }
+                PatchOp::Insert { index, values } => {
+                    vec.splice(*index..*index, values.iter().cloned());
+                }
+                PatchOp::Remove { index, len } => {
+                    vec.drain(*index..*index + *len);
+                }
+            }
+        }
+    }
+
+    /// Returns a new Vec with the changes applied to the given original items.
+    ///
+    /// Panics if an index of the patch is out of bounds for the original items.
+    pub fn apply_to(&self, original: &[T]) -> Vec<T> {
+        let mut result = Vec::with_capacity(original.len());
+        // Index of the first original item not yet copied or skipped.
+        let mut copied = 0;
+        for op in self.ops.iter() {
+            match op {
+                PatchOp::Replace { index, values } => {
+                    result.extend_from_slice(&original[copied..*index]);
+                    result.extend_from_slice(values);
+                    copied = *index + values.len();
+                }
+                PatchOp::Insert { index, values } => {
+                    result.extend_from_slice(&original[copied..*index]);
+                    result.extend_from_slice(values);
+                    copied = *index;
+                }
+                PatchOp::Remove { index, len } => {
+                    result.extend_from_slice(&original[copied..*index]);
+                    copied = *index + *len;
+                }
+            }
+        }
+        result.extend_from_slice(&original[copied..]);
+        result
+    }
+}
+
+impl<T> Patch<T> {
+    /// A patch removing the items before start and from end onwards.
+    pub(crate) fn narrowing(original_len: usize, start: usize, end: usize) -> Patch<T> {
+        let mut ops = Vec::new();
+        if start > 0 {
+            ops.push(PatchOp::Remove {
+                index: 0,
+                len: start,
+            });
+        }
+        if end < original_len {
+            ops.push(PatchOp::Remove {
+                index: end,
+                len: original_len - end,
+            });
+        }
+        Patch { ops }
+    }
+}
+
+/// Above this number of inserted and removed items, diff falls back to comparing
+/// the items at the same positions, to keep the cost linear.
+const MAX_EDIT_DISTANCE: usize = 128;
+
+/// A step of an edit script, going from the original items to the current ones.
+#[derive(Clone, Copy, PartialEq)]
+enum Edit {
+    Keep,
+    Remove,
+    // Insert the current item at the given index
+    Insert(usize),
+}
+
+/// Compute a patch which turns original into current.
+///
+/// The common prefix and suffix are skipped. The remaining items are diffed using
+/// Myers' algorithm, as long as the number of inserted and removed items is small.
+/// Otherwise the items at the same position are compared one by one, and the excess
+/// items are inserted or removed at the end.
+pub(crate) fn diff<T: Clone + PartialEq>(original: &[T], current: &[T]) -> Patch<T> {
+    let prefix = original
+        .iter()
+        .zip(current)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let max_suffix = original.len().min(current.len()) - prefix;
+    let suffix = original
+        .iter()
+        .rev()
+        .zip(current.iter().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let original_middle = &original[prefix..original.len() - suffix];
+    let current_middle = &current[prefix..current.len() - suffix];
+
+    let edits = shortest_edit_script(original_middle, current_middle, MAX_EDIT_DISTANCE)
+        .unwrap_or_else(|| positional_edit_script(original_middle, current_middle));
+    Patch {
+        ops: edits_to_ops(&edits, prefix, current_middle),
+    }
+}
+
+/// The edit script replacing the items at the same positions, and inserting or
+/// removing the excess items at the end.
+fn positional_edit_script<T: PartialEq>(original: &[T], current: &[T]) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(original.len() + current.len());
+    for (index, (a, b)) in original.iter().zip(current).enumerate() {
+        if a == b {
+            edits.push(Edit::Keep);
+        } else {
+            edits.push(Edit::Remove);
+            edits.push(Edit::Insert(index));
+        }
+    }
+    edits.extend((current.len()..original.len()).map(|_| Edit::Remove));
+    edits.extend((original.len()..current.len()).map(Edit::Insert));
+    edits
+}
+
+/// Myers' shortest edit script, or None if it would insert and remove more than
+/// max_distance items.
+fn shortest_edit_script<T: PartialEq>(
+    original: &[T],
+    current: &[T],
+    max_distance: usize,
+) -> Option<Vec<Edit>> {
+    let n = original.len() as isize;
+    let m = current.len() as isize;
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    // v[offset + k] is the furthest x reached on diagonal k = x - y.
+    let mut v = vec![0isize; 2 * max + 3];
+    // For each distance d, the diagonals -d..=d of v before that distance was explored.
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    for d in 0..=max.min(max_distance) as isize {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d
+                || (k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize])
+            {
+                v[(offset + k + 1) as usize]
+            } else {
+                v[(offset + k - 1) as usize] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && original[x as usize] == current[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(offset + k) as usize] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+        }
+    }
+    None
+}
+
+/// Walk back through the trace of shortest_edit_script, from the end of both slices
+/// to the start, and return the edits in forward order.
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().skip(1).rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        if prev_k == k + 1 {
+            y -= 1;
+            edits.push(Edit::Insert(y as usize));
+        } else {
+            x -= 1;
+            edits.push(Edit::Remove);
+        }
+    }
+    edits.extend((0..x).map(|_| Edit::Keep));
+    edits.reverse();
+    edits
+}
+
+/// Convert an edit script for the items after the common prefix into patch operations.
+fn edits_to_ops<T: Clone>(edits: &[Edit], prefix: usize, current: &[T]) -> Vec<PatchOp<T>> {
+    let mut ops = Vec::new();
+    let mut index = prefix;
+    let mut edits = edits.iter().peekable();
+    while let Some(&edit) = edits.next() {
+        if edit == Edit::Keep {
+            index += 1;
+            continue;
+        }
+        // A run of removed and inserted items, which is turned into a replacement
+        // followed by the excess removals or insertions.
+        let (mut removed, mut inserted) = (0, Vec::new());
+        let mut edit = Some(edit);
+        while let Some(run_edit) = edit {
+            match run_edit {
+                Edit::Remove => removed += 1,
+                Edit::Insert(at) => inserted.push(current[at].clone()),
+                Edit::Keep => break,
+            }
+            edit = edits.next_if(|&&e| e != Edit::Keep).copied();
+        }
+        let replaced = removed.min(inserted.len());
+        let excess = inserted.split_off(replaced);
+        if replaced > 0 {
+            ops.push(PatchOp::Replace {
+                index,
+                values: inserted,
+            });
+        }
+        if removed > replaced {
+            ops.push(PatchOp::Remove {
+                index: index + replaced,
+                len: removed - replaced,
+            });
+        }
+        if !excess.is_empty() {
+            ops.push(PatchOp::Insert {
+                index: index + replaced,
+                values: excess,
+            });
+        }
+        index += removed;
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, shortest_edit_script, PatchOp};
+
+    #[test]
+    fn test_diff() {
+        let original = vec![1, 2, 3, 4, 5, 6];
+        let current = vec![1, 20, 30, 4, 7, 5, 6];
+        let patch = diff(&original, &current);
+        assert_eq!(
+            patch.ops(),
+            &[
+                PatchOp::Replace {
+                    index: 1,
+                    values: vec![20, 30]
+                },
+                PatchOp::Insert {
+                    index: 4,
+                    values: vec![7]
+                },
+            ]
+        );
+        assert_eq!(patch.apply_to(&original), current);
+
+        let current = vec![2, 3, 5];
+        let patch = diff(&original, &current);
+        assert_eq!(patch.apply_to(&original), current);
+        let mut replica = original.clone();
+        patch.apply(&mut replica);
+        assert_eq!(replica, current);
+
+        assert!(diff(&original, &original).is_empty());
+        assert!(shortest_edit_script(&original, &current, 2).is_none());
+        assert_eq!(diff(&[], &original).apply_to(&[]), original);
+        assert!(diff(&original, &[]).apply_to(&original).is_empty());
+    }
+
+    #[test]
+    fn test_diff_large_edit_distance() {
+        let original: Vec<u32> = (0..300).collect();
+        let current: Vec<u32> = (0..300).rev().chain(0..10).collect();
+        let patch = diff(&original, &current);
+        assert_eq!(patch.apply_to(&original), current);
+        let mut replica = original.clone();
+        patch.apply(&mut replica);
+        assert_eq!(replica, current);
+    }
+}
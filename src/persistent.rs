@@ -0,0 +1,551 @@
+//! A persistent vector, which shares structure between clones.
+
+use crate::FastForeachItem;
+use std::ops::{Deref, DerefMut, Index};
+use std::sync::Arc;
+
+const BITS: usize = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+#[derive(Clone)]
+enum Node<'extvec, T> {
+    Branch(Vec<Arc<Node<'extvec, T>>>),
+    Leaf(Vec<T>),
+    // A leaf whose items are still borrowed from the initial slice.
+    Borrowed(&'extvec [T]),
+}
+
+impl<'extvec, T: Clone> Node<'extvec, T> {
+    /// The items of a leaf.
+    fn items(&self) -> &[T] {
+        match self {
+            Node::Leaf(items) => items,
+            Node::Borrowed(items) => items,
+            Node::Branch(_) => unreachable!(),
+        }
+    }
+    /// Clone the items of a borrowed leaf, and return them.
+    fn owned_items(&mut self) -> &mut Vec<T> {
+        if let Node::Borrowed(items) = self {
+            *self = Node::Leaf(items.to_vec());
+        }
+        match self {
+            Node::Leaf(items) => items,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A persistent vector, for keeping many versions of a large vector.
+///
+/// The items are stored in a trie with 32 items per leaf and 32 children per branch.
+/// Cloning a CowVecPersistent is O(1), since the clones share all nodes. Mutating
+/// an item in one of the clones only copies the path from the root to the leaf
+/// containing it, O(log n), instead of the whole vector.
+///
+/// When created from a borrowed Vec or slice, the leaves borrow it, so only the
+/// leaves which are written to are ever cloned.
+///
+/// This is a separate type rather than a backend of [CowVec](crate::CowVec), since
+/// CowVec dereferences to a slice, which requires the items to be stored contiguously.
+/// It offers the same kind of access methods as the other CowVec types.
+///
+/// ```
+/// # use cow_vec_item::CowVecPersistent;
+/// let big_vec: Vec<u32> = (0..10_000).collect();
+/// let mut current = CowVecPersistent::from(&big_vec);
+/// let mut history = Vec::new();
+/// for version in 0..10 {
+///     history.push(current.clone()); // O(1)
+///     current.set(version, 0); // Only copies the path to the leaf
+/// }
+/// assert_eq!(history[5][5], 5);
+/// assert_eq!(current[5], 0);
+/// ```
+pub struct CowVecPersistent<'extvec, T> {
+    root: Arc<Node<'extvec, T>>,
+    len: usize,
+    // The number of index bits above the leaf level. 0 means the root is a leaf.
+    shift: usize,
+}
+
+impl<'extvec, T> Clone for CowVecPersistent<'extvec, T> {
+    fn clone(&self) -> Self {
+        CowVecPersistent {
+            root: self.root.clone(),
+            len: self.len,
+            shift: self.shift,
+        }
+    }
+}
+
+/// Argument to the user supplied closure in fast_for_each_mut.
+struct PersistentForEachItem<'a, 'extvec, T: Clone> {
+    vec: &'a mut CowVecPersistent<'extvec, T>,
+    // The items of the leaf being visited. Only written to once 'owned' is set, which
+    // means the path to the leaf has been copied and it now points into the copy.
+    leaf: *mut [T],
+    owned: bool,
+    // Index of the first item of the leaf
+    leaf_start: usize,
+    offset: usize,
+}
+
+impl<'a, 'extvec, T: Clone> Deref for PersistentForEachItem<'a, 'extvec, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safe since the leaf is part of vec, which cannot be changed while borrowed
+        // by this item, other than through deref_mut, which updates the pointer.
+        unsafe { &(*self.leaf)[self.offset] }
+    }
+}
+impl<'a, 'extvec, T: Clone> DerefMut for PersistentForEachItem<'a, 'extvec, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        if !self.owned {
+            self.leaf = self.vec.make_mut_leaf(self.leaf_start) as *mut [T];
+            self.owned = true;
+        }
+        // Safe since the leaf is no longer shared with other clones or the borrowed
+        // slice, and is only reachable through vec, see deref.
+        unsafe { &mut (*self.leaf)[self.offset] }
+    }
+}
+impl<'a, 'extvec, T: Clone> FastForeachItem for PersistentForEachItem<'a, 'extvec, T> {}
+
+impl<'extvec, T: Clone> Default for CowVecPersistent<'extvec, T> {
+    fn default() -> Self {
+        CowVecPersistent::new()
+    }
+}
+
+impl<'extvec, T: Clone> CowVecPersistent<'extvec, T> {
+    /// Creates an empty CowVecPersistent.
+    pub fn new() -> CowVecPersistent<'extvec, T> {
+        CowVecPersistent {
+            root: Arc::new(Node::Leaf(Vec::new())),
+            len: 0,
+            shift: 0,
+        }
+    }
+    /// Creates a CowVecPersistent taking ownership of the items of the given Vec.
+    pub fn from_vec(vec: Vec<T>) -> CowVecPersistent<'extvec, T> {
+        let len = vec.len();
+        let mut items = vec.into_iter();
+        let mut leaves = Vec::new();
+        loop {
+            let leaf: Vec<T> = items.by_ref().take(WIDTH).collect();
+            if leaf.is_empty() {
+                break;
+            }
+            leaves.push(Arc::new(Node::Leaf(leaf)));
+        }
+        CowVecPersistent::from_leaves(leaves, len)
+    }
+    /// Creates a CowVecPersistent which borrows the given Vec. Leaves are only cloned
+    /// when they are written to.
+    #[allow(clippy::ptr_arg)]
+    pub fn from(vec: &'extvec Vec<T>) -> CowVecPersistent<'extvec, T> {
+        CowVecPersistent::from_slice(vec)
+    }
+    /// Creates a CowVecPersistent which borrows the given slice. Leaves are only cloned
+    /// when they are written to.
+    pub fn from_slice(slice: &'extvec [T]) -> CowVecPersistent<'extvec, T> {
+        let leaves = slice
+            .chunks(WIDTH)
+            .map(|chunk| Arc::new(Node::Borrowed(chunk)))
+            .collect();
+        CowVecPersistent::from_leaves(leaves, slice.len())
+    }
+
+    /// Build the branches above the given full leaves, which hold len items.
+    fn from_leaves(
+        mut nodes: Vec<Arc<Node<'extvec, T>>>,
+        len: usize,
+    ) -> CowVecPersistent<'extvec, T> {
+        let mut shift = 0;
+        while nodes.len() > 1 {
+            let mut children = nodes.into_iter();
+            nodes = Vec::new();
+            loop {
+                let branch: Vec<_> = children.by_ref().take(WIDTH).collect();
+                if branch.is_empty() {
+                    break;
+                }
+                nodes.push(Arc::new(Node::Branch(branch)));
+            }
+            shift += BITS;
+        }
+        match nodes.pop() {
+            Some(root) => CowVecPersistent { root, len, shift },
+            None => CowVecPersistent::new(),
+        }
+    }
+
+    /// The number of items.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Returns true if there are no items.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Returns a reference to the item at the given index, or None if out of bounds.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+        self.leaf(index).get(index & MASK)
+    }
+    /// Returns a mutable reference to the item at the given index, or None if out of
+    /// bounds. Nodes on the path to the item which are shared with other clones are copied.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+        Some(self.make_mut(index))
+    }
+    /// Set the item at the given index.
+    ///
+    /// Panics if index is out of bounds.
+    pub fn set(&mut self, index: usize, value: T) {
+        if index >= self.len {
+            panic!(
+                "cow_vec_item: index {} out of range for CowVecPersistent of length {}",
+                index, self.len
+            );
+        }
+        *self.make_mut(index) = value;
+    }
+    /// Iterate over all items. Each leaf is looked up from the root, so this is
+    /// O(n / 32 * log n).
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        (0..self.len)
+            .step_by(WIDTH)
+            .flat_map(move |start| self.leaf(start).iter())
+    }
+
+    /// Appends an item.
+    pub fn push(&mut self, value: T) {
+        if self.len == 1 << (self.shift + BITS) {
+            let old_root = std::mem::replace(&mut self.root, Arc::new(Node::Branch(Vec::new())));
+            self.root = Arc::new(Node::Branch(vec![old_root]));
+            self.shift += BITS;
+        }
+        let index = self.len;
+        let mut node = Arc::make_mut(&mut self.root);
+        let mut level = self.shift;
+        loop {
+            match node {
+                Node::Branch(children) => {
+                    let child_index = (index >> level) & MASK;
+                    if child_index == children.len() {
+                        children.push(Arc::new(if level == BITS {
+                            Node::Leaf(Vec::with_capacity(WIDTH))
+                        } else {
+                            Node::Branch(Vec::new())
+                        }));
+                    }
+                    node = Arc::make_mut(&mut children[child_index]);
+                    level -= BITS;
+                }
+                leaf => {
+                    leaf.owned_items().push(value);
+                    break;
+                }
+            }
+        }
+        self.len += 1;
+    }
+    /// Removes the last item and returns it, or None if empty.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let value = pop_node(Arc::make_mut(&mut self.root));
+        self.len -= 1;
+        while self.shift > 0 {
+            let only_child = match &*self.root {
+                Node::Branch(children) if children.len() == 1 => children[0].clone(),
+                _ => break,
+            };
+            self.root = only_child;
+            self.shift -= BITS;
+        }
+        Some(value)
+    }
+
+    /// Like [CowVec::fast_for_each_mut](crate::CowVec::fast_for_each_mut). Only the
+    /// paths to the leaves which are mutated are copied.
+    ///
+    /// Like iter, each leaf is looked up from the root once, and once more on the
+    /// first write to it.
+    pub fn fast_for_each_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut dyn FastForeachItem<Target = T>),
+    {
+        let len = self.len;
+        for leaf_start in (0..len).step_by(WIDTH) {
+            let mut item = PersistentForEachItem {
+                leaf: self.leaf(leaf_start) as *const [T] as *mut [T],
+                vec: self,
+                owned: false,
+                leaf_start,
+                offset: 0,
+            };
+            for offset in 0..(len - leaf_start).min(WIDTH) {
+                item.offset = offset;
+                f(&mut item);
+            }
+        }
+    }
+
+    /// Returns true if the item at the given index is stored in the same leaf in both
+    /// this CowVecPersistent and the other one, meaning no copy has been made.
+    ///
+    /// Panics if index is out of bounds in either of them.
+    pub fn shares_leaf_with(&self, other: &CowVecPersistent<'extvec, T>, index: usize) -> bool {
+        match (self.get(index), other.get(index)) {
+            (Some(item), Some(other_item)) => std::ptr::eq(item, other_item),
+            _ => panic!(
+                "cow_vec_item: index {} out of range for CowVecPersistent",
+                index
+            ),
+        }
+    }
+
+    /// Returns all the items as a Vec. Leaves not shared with other clones are moved,
+    /// the rest are cloned.
+    pub fn into_vec(self) -> Vec<T> {
+        let mut vec = Vec::with_capacity(self.len);
+        drain_into(self.root, &mut vec);
+        vec
+    }
+
+    /// The items of the leaf containing the given index, which must be in bounds.
+    fn leaf(&self, index: usize) -> &[T] {
+        let mut node = &*self.root;
+        let mut level = self.shift;
+        while let Node::Branch(children) = node {
+            node = &children[(index >> level) & MASK];
+            level -= BITS;
+        }
+        node.items()
+    }
+
+    fn make_mut(&mut self, index: usize) -> &mut T {
+        &mut self.make_mut_leaf(index)[index & MASK]
+    }
+
+    /// The items of the leaf containing the given index, which must be in bounds.
+    /// Nodes on the path to the leaf which are shared with other clones are copied.
+    fn make_mut_leaf(&mut self, index: usize) -> &mut [T] {
+        let mut node = Arc::make_mut(&mut self.root);
+        let mut level = self.shift;
+        loop {
+            match node {
+                Node::Branch(children) => {
+                    node = Arc::make_mut(&mut children[(index >> level) & MASK]);
+                    level -= BITS;
+                }
+                leaf => return leaf.owned_items(),
+            }
+        }
+    }
+}
+
+fn pop_node<T: Clone>(node: &mut Node<'_, T>) -> T {
+    match node {
+        Node::Leaf(items) => items
+            .pop()
+            .expect("cow_vec_item: empty leaf in CowVecPersistent"),
+        Node::Borrowed(items) => {
+            let (last, rest) = items
+                .split_last()
+                .expect("cow_vec_item: empty leaf in CowVecPersistent");
+            *items = rest;
+            last.clone()
+        }
+        Node::Branch(children) => {
+            let last = children
+                .last_mut()
+                .expect("cow_vec_item: empty branch in CowVecPersistent");
+            let value = pop_node(Arc::make_mut(last));
+            let last_is_empty = match &**last {
+                Node::Branch(children) => children.is_empty(),
+                leaf => leaf.items().is_empty(),
+            };
+            if last_is_empty {
+                children.pop();
+            }
+            value
+        }
+    }
+}
+
+fn drain_into<T: Clone>(node: Arc<Node<'_, T>>, vec: &mut Vec<T>) {
+    match Arc::try_unwrap(node) {
+        Ok(Node::Leaf(items)) => vec.extend(items),
+        Ok(Node::Borrowed(items)) => vec.extend_from_slice(items),
+        Ok(Node::Branch(children)) => {
+            for child in children {
+                drain_into(child, vec);
+            }
+        }
+        Err(shared) => match &*shared {
+            Node::Branch(children) => {
+                for child in children {
+                    drain_into(child.clone(), vec);
+                }
+            }
+            leaf => vec.extend_from_slice(leaf.items()),
+        },
+    }
+}
+
+impl<'extvec, T: Clone> Index<usize> for CowVecPersistent<'extvec, T> {
+    type Output = T;
+    fn index(&self, index: usize) -> &T {
+        match self.get(index) {
+            Some(item) => item,
+            None => panic!(
+                "cow_vec_item: index {} out of range for CowVecPersistent of length {}",
+                index, self.len
+            ),
+        }
+    }
+}
+
+impl<'extvec, T: Clone> Extend<T> for CowVecPersistent<'extvec, T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.push(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CowVecPersistent;
+
+    #[test]
+    fn test_persistent_push_pop_matches_vec() {
+        let mut temp = CowVecPersistent::new();
+        let mut model = Vec::new();
+        for i in 0..2000u32 {
+            temp.push(i);
+            model.push(i);
+        }
+        assert_eq!(temp.len(), 2000);
+        assert_eq!(temp.iter().copied().collect::<Vec<_>>(), model);
+        assert_eq!(temp[1234], 1234);
+        assert_eq!(temp.get(2000), None);
+        while let Some(item) = model.pop() {
+            assert_eq!(temp.pop(), Some(item));
+            if model.len() % 97 == 0 {
+                assert_eq!(temp.iter().copied().collect::<Vec<_>>(), model);
+            }
+        }
+        assert_eq!(temp.pop(), None);
+        assert!(temp.is_empty());
+        temp.push(7);
+        assert_eq!(temp.into_vec(), vec![7]);
+    }
+
+    #[test]
+    fn test_persistent_from_vec() {
+        for len in [0usize, 1, 31, 32, 33, 1024, 1025, 5000].iter() {
+            let v: Vec<usize> = (0..*len).collect();
+            let mut temp = CowVecPersistent::from_slice(&v);
+            assert_eq!(temp.iter().copied().collect::<Vec<_>>(), v);
+            temp.push(*len);
+            assert_eq!(temp[*len], *len);
+            assert_eq!(temp.pop(), Some(*len));
+            assert_eq!(temp.into_vec(), v);
+        }
+    }
+
+    #[test]
+    fn test_persistent_clone_shares_structure() {
+        let original = CowVecPersistent::from_vec((0..5000u32).collect());
+        let mut modified = original.clone();
+        assert!(modified.shares_leaf_with(&original, 100));
+        modified.set(100, 0);
+        assert!(!modified.shares_leaf_with(&original, 100));
+        assert!(!modified.shares_leaf_with(&original, 101));
+        assert!(modified.shares_leaf_with(&original, 4000));
+        assert_eq!(original[100], 100);
+        assert_eq!(modified[100], 0);
+
+        let mut versions = vec![original.clone()];
+        for i in 0..10 {
+            let mut next = versions[versions.len() - 1].clone();
+            *next.get_mut(i * 500).unwrap() += 1;
+            versions.push(next);
+        }
+        assert_eq!(versions[0][4500], 4500);
+        assert_eq!(versions[10][4500], 4501);
+        assert_eq!(versions[5][4500], 4500);
+        assert_eq!(original.into_vec(), (0..5000u32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_persistent_borrows_leaves() {
+        let v: Vec<u32> = (0..1000).collect();
+        let mut temp = CowVecPersistent::from(&v);
+        assert!(std::ptr::eq(&temp[500], &v[500]));
+        temp.set(500, 0);
+        assert!(!std::ptr::eq(&temp[500], &v[500]));
+        assert!(std::ptr::eq(&temp[501 + 32], &v[501 + 32]));
+        assert_eq!(temp[501], 501);
+        assert_eq!(temp.pop(), Some(999));
+        temp.push(1);
+        assert_eq!(temp[999], 1);
+        let mut expected = v.clone();
+        expected[500] = 0;
+        expected[999] = 1;
+        assert_eq!(temp.iter().copied().collect::<Vec<_>>(), expected);
+        assert_eq!(temp.into_vec(), expected);
+        assert_eq!(v, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_persistent_fast_for_each() {
+        let original = CowVecPersistent::from_vec((0..100i32).collect());
+        let mut modified = original.clone();
+        modified.fast_for_each_mut(|item| {
+            if **item == 70 {
+                **item = -70;
+            }
+        });
+        assert!(modified.shares_leaf_with(&original, 0));
+        assert!(!modified.shares_leaf_with(&original, 70));
+        assert_eq!(modified[70], -70);
+        assert_eq!(original[70], 70);
+        let mut sum = 0;
+        modified.fast_for_each_mut(|item| sum += **item);
+        assert_eq!(sum, (0..100).sum::<i32>() - 140);
+
+        let mut expected: Vec<i32> = modified.iter().copied().collect();
+        modified.fast_for_each_mut(|item| {
+            if **item % 3 == 0 {
+                **item *= 10;
+                **item += 1;
+            }
+        });
+        for item in expected.iter_mut().filter(|item| **item % 3 == 0) {
+            *item = *item * 10 + 1;
+        }
+        assert_eq!(modified.into_vec(), expected);
+        assert_eq!(original.into_vec(), (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_persistent_zero_size() {
+        let mut temp = CowVecPersistent::from_vec(vec![(); 100]);
+        temp.extend(vec![(); 50]);
+        temp.set(120, ());
+        assert_eq!(temp.len(), 150);
+        assert_eq!(temp.iter().count(), 150);
+    }
+}
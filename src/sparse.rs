@@ -0,0 +1,517 @@
+//! A copy-on-write vector which records edits sparsely instead of cloning.
+
+use crate::{CowVec, FastForeachItem, OwnedForEachItem, WrapperState};
+use std::collections::BTreeMap;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut, Index, IndexMut};
+
+/// The density threshold used unless set_density_threshold is called.
+const DEFAULT_DENSITY_THRESHOLD: f64 = 0.25;
+
+enum SparseContent<'extvec, T> {
+    Overlay {
+        base: &'extvec [T],
+        edits: BTreeMap<usize, T>,
+    },
+    Owned(Vec<T>),
+}
+
+/// A copy-on-write vector for large borrowed Vecs of which only a few items are changed.
+///
+/// Instead of cloning the entire borrowed Vec on the first write, as
+/// [CowVec](crate::CowVec) does, each written item is cloned on its own into a sparse
+/// overlay. This applies to writes through get_mut, set, iter_mut and fast_for_each_mut.
+/// Reads using get, iter or indexing check the overlay first. The contents are only
+/// materialized into a Vec when requested using to_mut, into_owned or into_cow_vec,
+/// or when a write would take the number of edited items above the density threshold.
+///
+/// Since the edited items are not stored next to the rest, CowVecSparse does not
+/// dereference to a slice. Use to_mut to get mutable access to all the contents.
+///
+/// ```
+/// # use cow_vec_item::CowVecSparse;
+/// let big_vec: Vec<u32> = (0..1000).collect();
+/// let mut sparse = CowVecSparse::from(&big_vec);
+/// sparse.fast_for_each_mut(|item| {
+///     if **item == 500 {
+///         **item = 0; // Only this item is cloned
+///     }
+/// });
+/// assert_eq!(sparse[500], 0);
+/// assert_eq!(sparse.edit_count(), 1);
+/// assert_eq!(sparse.is_owned(), false);
+/// ```
+pub struct CowVecSparse<'extvec, T> {
+    content: SparseContent<'extvec, T>,
+    density_threshold: f64,
+    bad_wrapper_use_detector: WrapperState,
+}
+
+/// Argument to the user supplied closure in fast_for_each_mut, while the overlay is used.
+struct SparseForEachItem<'a, 'extvec, T> {
+    sparse: &'a mut CowVecSparse<'extvec, T>,
+    index: usize,
+}
+
+impl<'a, 'extvec, T: Clone> Deref for SparseForEachItem<'a, 'extvec, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.sparse[self.index]
+    }
+}
+impl<'a, 'extvec, T: Clone> DerefMut for SparseForEachItem<'a, 'extvec, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        let index = self.index;
+        self.sparse.get_mut(index).unwrap()
+    }
+}
+impl<'a, 'extvec, T: Clone> FastForeachItem for SparseForEachItem<'a, 'extvec, T> {}
+
+/// Mutable iterator over a [CowVecSparse](crate::CowVecSparse), returned by iter_mut.
+///
+/// Like [CowVecIter](crate::CowVecIter), only one item may be alive at a time.
+pub struct CowVecSparseIter<'extvec, 'sparse, T> {
+    sparse: *mut CowVecSparse<'extvec, T>,
+    index: usize,
+    end: usize,
+    phantom: PhantomData<&'sparse mut ()>,
+}
+
+/// A placeholder representing an item being iterated over by
+/// [CowVecSparseIter](crate::CowVecSparseIter). Writing to it clones only this item
+/// into the overlay.
+pub struct CowVecSparseItem<'extvec, 'sparse, T> {
+    sparse: *mut CowVecSparse<'extvec, T>,
+    index: usize,
+    phantom: PhantomData<&'sparse mut ()>,
+}
+
+impl<'extvec, 'sparse, T> Drop for CowVecSparseItem<'extvec, 'sparse, T> {
+    fn drop(&mut self) {
+        // Safe since the CowVecSparse is mutably borrowed for 'sparse.
+        unsafe { (*self.sparse).bad_wrapper_use_detector = WrapperState::Dead };
+    }
+}
+
+impl<'extvec, 'sparse, T: Clone> Deref for CowVecSparseItem<'extvec, 'sparse, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safe since the CowVecSparse is mutably borrowed for 'sparse, and only one
+        // item is alive at a time.
+        unsafe { &(&*self.sparse)[self.index] }
+    }
+}
+
+impl<'extvec, 'sparse, T: Clone> DerefMut for CowVecSparseItem<'extvec, 'sparse, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safe for the same reasons as deref. The returned reference borrows the item
+        // mutably, so no other access can happen until it is gone.
+        unsafe { (&mut *self.sparse).get_mut(self.index).unwrap() }
+    }
+}
+
+impl<'extvec, 'sparse, T: Clone> Iterator for CowVecSparseIter<'extvec, 'sparse, T> {
+    type Item = CowVecSparseItem<'extvec, 'sparse, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Safe since the CowVecSparse is mutably borrowed for 'sparse.
+        let detector = unsafe { &mut (*self.sparse).bad_wrapper_use_detector };
+        if *detector != WrapperState::Dead {
+            panic!("cow_vec_item: The placeholders returned by the mutable iterator of CowVecSparse must not be retained. Only one wrapper can be alive at a time, but next() was called while the previous value had not been dropped.");
+        }
+        if self.index == self.end {
+            return None;
+        }
+        *detector = WrapperState::Alive;
+        let item = CowVecSparseItem {
+            sparse: self.sparse,
+            index: self.index,
+            phantom: PhantomData,
+        };
+        self.index += 1;
+        Some(item)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let size = self.end - self.index;
+        (size, Some(size))
+    }
+}
+
+impl<'extvec, 'sparse, T: Clone> ExactSizeIterator for CowVecSparseIter<'extvec, 'sparse, T> {}
+
+impl<'extvec, 'sparse, T: Clone> FusedIterator for CowVecSparseIter<'extvec, 'sparse, T> {}
+
+impl<'extvec, T: Clone> CowVecSparse<'extvec, T> {
+    /// Creates a CowVecSparse which borrows the given Vec.
+    #[allow(clippy::ptr_arg)]
+    pub fn from(vec: &'extvec Vec<T>) -> CowVecSparse<'extvec, T> {
+        CowVecSparse::from_slice(vec)
+    }
+    /// Creates a CowVecSparse which borrows the given slice.
+    pub fn from_slice(slice: &'extvec [T]) -> CowVecSparse<'extvec, T> {
+        CowVecSparse {
+            content: SparseContent::Overlay {
+                base: slice,
+                edits: BTreeMap::new(),
+            },
+            density_threshold: DEFAULT_DENSITY_THRESHOLD,
+            bad_wrapper_use_detector: WrapperState::Dead,
+        }
+    }
+
+    /// Set the fraction (0.0 to 1.0) of edited items above which the contents are
+    /// materialized into a Vec. The default is 0.25.
+    ///
+    /// Before a write adds an item to the overlay, the contents are materialized if
+    /// the overlay would then hold more than threshold * len items. Setting a lower
+    /// threshold materializes immediately if the overlay already holds more.
+    pub fn set_density_threshold(&mut self, threshold: f64) {
+        self.density_threshold = threshold;
+        if let SparseContent::Overlay { edits, .. } = &self.content {
+            if self.exceeds_density(edits.len()) {
+                self.to_mut();
+            }
+        }
+    }
+
+    /// Returns true if the contents have been materialized into an owned Vec.
+    pub fn is_owned(&self) -> bool {
+        matches!(self.content, SparseContent::Owned(_))
+    }
+    /// Returns the number of items in the overlay. This is 0 after the contents have
+    /// been materialized.
+    pub fn edit_count(&self) -> usize {
+        match &self.content {
+            SparseContent::Owned(_) => 0,
+            SparseContent::Overlay { edits, .. } => edits.len(),
+        }
+    }
+    /// The number of items.
+    pub fn len(&self) -> usize {
+        match &self.content {
+            SparseContent::Owned(v) => v.len(),
+            SparseContent::Overlay { base, .. } => base.len(),
+        }
+    }
+    /// Returns true if there are no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns a reference to the item at the given index, or None if out of bounds.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        match &self.content {
+            SparseContent::Owned(v) => v.get(index),
+            SparseContent::Overlay { base, edits } => {
+                let base_item = base.get(index)?;
+                Some(edits.get(&index).unwrap_or(base_item))
+            }
+        }
+    }
+    /// Iterate over all items.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        let (items, edits) = match &self.content {
+            SparseContent::Owned(v) => (&v[..], None),
+            SparseContent::Overlay { base, edits } => (*base, Some(edits)),
+        };
+        items
+            .iter()
+            .enumerate()
+            .map(move |(index, item)| edits.and_then(|edits| edits.get(&index)).unwrap_or(item))
+    }
+
+    /// Set the item at the given index. While the overlay is used, this
+    /// only records the new value in the overlay.
+    ///
+    /// Panics if index is out of bounds.
+    pub fn set(&mut self, index: usize, value: T) {
+        *self.get_mut(index).unwrap_or_else(|| {
+            panic!(
+                "cow_vec_item: index {} out of range for CowVecSparse",
+                index
+            )
+        }) = value;
+    }
+
+    /// Returns a mutable reference to the item at the given index, or None if out of bounds.
+    /// While the overlay is used, only the item itself is cloned.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len() {
+            return None;
+        }
+        if let SparseContent::Overlay { edits, .. } = &self.content {
+            // Materialize first if this write would take us over the threshold.
+            if !edits.contains_key(&index) && self.exceeds_density(edits.len() + 1) {
+                self.to_mut();
+            }
+        }
+        match &mut self.content {
+            SparseContent::Owned(v) => v.get_mut(index),
+            SparseContent::Overlay { base, edits } => {
+                let base_item = &base[index];
+                Some(edits.entry(index).or_insert_with(|| base_item.clone()))
+            }
+        }
+    }
+
+    /// Like [CowVec::iter_mut](crate::CowVec::iter_mut). While the overlay is used,
+    /// each mutated item is cloned into the overlay on its own, just like get_mut.
+    pub fn iter_mut<'sparse>(&'sparse mut self) -> CowVecSparseIter<'extvec, 'sparse, T> {
+        if self.bad_wrapper_use_detector != WrapperState::Dead {
+            unreachable!("cow_vec_item: iter_mut was called while wrappers from a previous iter_mut were still alive! I had expected rust ownership rules to make this impossible. Please file a bug!");
+        }
+        CowVecSparseIter {
+            end: self.len(),
+            sparse: self as *mut CowVecSparse<'extvec, T>,
+            index: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Like [CowVec::fast_for_each_mut](crate::CowVec::fast_for_each_mut). While the
+    /// overlay is used, each mutated item is cloned into the overlay on its own, just
+    /// like get_mut.
+    pub fn fast_for_each_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut dyn FastForeachItem<Target = T>),
+    {
+        let len = self.len();
+        let mut index = 0;
+        while index < len {
+            if let SparseContent::Owned(v) = &mut self.content {
+                for item in v[index..].iter_mut() {
+                    f(&mut OwnedForEachItem::untracked(item));
+                }
+                return;
+            }
+            f(&mut SparseForEachItem {
+                sparse: self,
+                index,
+            });
+            index += 1;
+        }
+    }
+
+    /// Materialize the contents into an owned Vec, and return a mutable reference to it.
+    pub fn to_mut(&mut self) -> &mut Vec<T> {
+        if let SparseContent::Overlay { base, edits } = &mut self.content {
+            let mut vec = base.to_vec();
+            for (index, value) in std::mem::take(edits) {
+                vec[index] = value;
+            }
+            self.content = SparseContent::Owned(vec);
+        }
+        match &mut self.content {
+            SparseContent::Owned(v) => v,
+            SparseContent::Overlay { .. } => unreachable!(),
+        }
+    }
+    /// Returns the contents as an owned Vec, materializing if needed.
+    pub fn into_owned(mut self) -> Vec<T> {
+        self.to_mut();
+        match self.content {
+            SparseContent::Owned(v) => v,
+            SparseContent::Overlay { .. } => unreachable!(),
+        }
+    }
+    /// Converts into a CowVec. If nothing was written, the returned CowVec still
+    /// borrows the initial Vec.
+    pub fn into_cow_vec(self) -> CowVec<'extvec, T> {
+        match self.content {
+            SparseContent::Overlay { base, edits } if edits.is_empty() => CowVec::from_slice(base),
+            _ => CowVec::from_owned(self.into_owned()),
+        }
+    }
+
+    fn exceeds_density(&self, edit_count: usize) -> bool {
+        edit_count as f64 > self.density_threshold * self.len() as f64
+    }
+}
+
+impl<'extvec, T: Clone> Index<usize> for CowVecSparse<'extvec, T> {
+    type Output = T;
+    fn index(&self, index: usize) -> &T {
+        match self.get(index) {
+            Some(item) => item,
+            None => panic!(
+                "cow_vec_item: index {} out of range for CowVecSparse of length {}",
+                index,
+                self.len()
+            ),
+        }
+    }
+}
+
+impl<'extvec, T: Clone> IndexMut<usize> for CowVecSparse<'extvec, T> {
+    /// Like get_mut, but panics if index is out of bounds.
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        let len = self.len();
+        match self.get_mut(index) {
+            Some(item) => item,
+            None => panic!(
+                "cow_vec_item: index {} out of range for CowVecSparse of length {}",
+                index, len
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CowVecSparse;
+
+    #[test]
+    fn test_sparse_writes_go_to_overlay() {
+        let v: Vec<i32> = (0..10).collect();
+        let mut temp = CowVecSparse::from(&v);
+        temp.set(3, 30);
+        *temp.get_mut(7).unwrap() += 70;
+        assert!(!temp.is_owned());
+        assert_eq!(temp.edit_count(), 2);
+        assert_eq!(temp[3], 30);
+        assert_eq!(temp[7], 77);
+        assert_eq!(temp.get(10), None);
+        assert_eq!(
+            temp.iter().copied().collect::<Vec<_>>(),
+            vec![0, 1, 2, 30, 4, 5, 6, 77, 8, 9]
+        );
+        assert_eq!(temp.into_owned(), vec![0, 1, 2, 30, 4, 5, 6, 77, 8, 9]);
+        assert_eq!(v, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sparse_fast_for_each() {
+        let v: Vec<i32> = (0..10).collect();
+        let mut temp = CowVecSparse::from(&v);
+        temp.fast_for_each_mut(|item| {
+            if **item % 5 == 0 {
+                **item += 100;
+            }
+        });
+        assert_eq!(temp.edit_count(), 2);
+        assert!(!temp.is_owned());
+        let mut seen = Vec::new();
+        temp.fast_for_each_mut(|item| seen.push(**item));
+        assert_eq!(seen, vec![100, 1, 2, 3, 4, 105, 6, 7, 8, 9]);
+        assert_eq!(temp.edit_count(), 2);
+    }
+
+    #[test]
+    fn test_sparse_density_threshold() {
+        let v: Vec<i32> = (0..10).collect();
+        let mut temp = CowVecSparse::from(&v);
+        temp.set_density_threshold(0.2);
+        temp.set(0, 10);
+        temp.set(0, 11);
+        temp.set(1, 12);
+        assert!(!temp.is_owned());
+        temp.set(2, 13);
+        assert!(temp.is_owned());
+        assert_eq!(temp.edit_count(), 0);
+        assert_eq!(
+            temp.iter().take(4).copied().collect::<Vec<_>>(),
+            vec![11, 12, 13, 3]
+        );
+
+        let mut temp = CowVecSparse::from(&v);
+        temp.set_density_threshold(0.2);
+        temp.fast_for_each_mut(|item| **item *= 2);
+        assert!(temp.is_owned());
+        assert_eq!(
+            temp.into_owned(),
+            v.iter().map(|x| x * 2).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_sparse_same_density_rule_for_all_writes() {
+        let v: Vec<i32> = (0..10).collect();
+        let mut by_get_mut = CowVecSparse::from(&v);
+        let mut by_for_each = CowVecSparse::from(&v);
+        let mut by_iter_mut = CowVecSparse::from(&v);
+        for temp in [&mut by_get_mut, &mut by_for_each, &mut by_iter_mut] {
+            temp.set_density_threshold(0.2);
+        }
+        for count in 1..=3 {
+            for index in 0..count {
+                *by_get_mut.get_mut(index).unwrap() += 1;
+            }
+            by_for_each.fast_for_each_mut(|item| {
+                if **item < count as i32 {
+                    **item += 1;
+                }
+            });
+            for mut item in by_iter_mut.iter_mut() {
+                if *item < count as i32 {
+                    *item += 1;
+                }
+            }
+            let owned = count > 2;
+            assert_eq!(by_get_mut.is_owned(), owned);
+            assert_eq!(by_for_each.is_owned(), owned);
+            assert_eq!(by_iter_mut.is_owned(), owned);
+        }
+    }
+
+    #[test]
+    fn test_sparse_iter_mut() {
+        let v: Vec<i32> = (0..10).collect();
+        let mut temp = CowVecSparse::from(&v);
+        for mut item in temp.iter_mut() {
+            if *item == 4 {
+                *item = 40;
+            }
+        }
+        assert_eq!(temp.edit_count(), 1);
+        assert_eq!(temp.iter_mut().len(), 10);
+        assert_eq!(temp[4], 40);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_sparse_iter_mut_retained_wrappers() {
+        let v = vec![1i32, 2, 3];
+        let mut temp = CowVecSparse::from(&v);
+        let _retained: Vec<_> = temp.iter_mut().collect();
+    }
+
+    #[test]
+    fn test_sparse_reads_do_not_materialize() {
+        let v: Vec<i32> = (0..10).collect();
+        let mut temp = CowVecSparse::from(&v);
+        temp[1] = 10;
+        temp[2] += 20;
+        assert_eq!(
+            temp.iter().take(3).copied().collect::<Vec<_>>(),
+            [0, 10, 22]
+        );
+        assert_eq!(temp[2], 22);
+        assert!(!temp.is_owned());
+        assert_eq!(temp.edit_count(), 2);
+
+        temp.to_mut().sort_by(|a, b| b.cmp(a));
+        assert!(temp.is_owned());
+        assert_eq!(temp.edit_count(), 0);
+        assert_eq!(temp[0], 22);
+    }
+
+    #[test]
+    fn test_sparse_into_cow_vec() {
+        let v = vec![1i32, 2, 3];
+        let temp = CowVecSparse::from(&v);
+        assert!(!temp.into_cow_vec().is_owned());
+
+        let mut temp = CowVecSparse::from(&v);
+        temp.set(1, 20);
+        let cowvec = temp.into_cow_vec();
+        assert_eq!(*cowvec, [1, 20, 3]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_sparse_set_out_of_bounds() {
+        let v = vec![1i32];
+        let mut temp = CowVecSparse::from(&v);
+        temp.set(1, 1);
+    }
+}